@@ -2,7 +2,16 @@ use eframe::egui;
 use egui::ViewportBuilder;
 use egui_plot::{Line, Plot, PlotPoints};
 use std::error::Error;
-use csv::Reader;
+use csv::{Reader, StringRecord};
+
+// Contributions to the simulated voltage in output.csv, stacked from the open-circuit voltage up
+const BREAKDOWN_COLUMNS: [(&str, &str); 5] = [
+    ("Open circuit voltage", "Open circuit"),
+    ("Voltage offset", "+ Offset"),
+    ("Ohmic overpotential", "+ Ohmic"),
+    ("Activation overpotential", "+ Activation"),
+    ("Concentration overpotential", "+ Concentration"),
+];
 
 struct PlotViewer {
     // Model data
@@ -12,64 +21,105 @@ struct PlotViewer {
     c2a_data: Vec<f64>,
     c1c_data: Vec<f64>,
     c0c_data: Vec<f64>,
+    breakdown_data: Vec<Vec<f64>>,
     // Experimental data
     exp_time: Vec<f64>,
     exp_voltage: Vec<f64>,
     // Display toggles
     show_voltage: bool,
     show_concentration: bool,
+    show_breakdown: bool,
     show_experimental: bool,
 }
 
+struct ModelData {
+    time: Vec<f64>,
+    voltage: Vec<f64>,
+    c1c: Vec<f64>,
+    c0c: Vec<f64>,
+    c1a: Vec<f64>,
+    c2a: Vec<f64>,
+    // One series per entry of BREAKDOWN_COLUMNS
+    breakdown: Vec<Vec<f64>>,
+}
+
+fn column_index(headers: &StringRecord, name: &str) -> Result<usize, Box<dyn Error>> {
+    headers.iter()
+        .position(|header| header.trim() == name)
+        .ok_or_else(|| format!("column '{}' not found", name).into())
+}
+
 impl PlotViewer {
     fn new() -> Result<Self, Box<dyn Error>> {
         println!("Reading model data from output.csv...");
-        let (time, voltage, c1c, c0c, c1a, c2a) = Self::read_model_data("output.csv")?;
+        let model = Self::read_model_data("output.csv")?;
 
         println!("Reading experimental data from data.csv...");
         let (exp_time, exp_voltage) = Self::read_experimental_data("data.csv")?;
 
         Ok(Self {
-            time_data: time,
-            voltage_data: voltage,
-            c1a_data: c1a,
-            c2a_data: c2a,
-            c1c_data: c1c,
-            c0c_data: c0c,
+            time_data: model.time,
+            voltage_data: model.voltage,
+            c1a_data: model.c1a,
+            c2a_data: model.c2a,
+            c1c_data: model.c1c,
+            c0c_data: model.c0c,
+            breakdown_data: model.breakdown,
             exp_time,
             exp_voltage,
             show_voltage: true,
             show_concentration: true,
+            show_breakdown: true,
             show_experimental: true,
         })
     }
 
-    fn read_model_data(file_path: &str) -> Result<(Vec<f64>, Vec<f64>, Vec<f64>, Vec<f64>, Vec<f64>, Vec<f64>), Box<dyn Error>> {
+    fn read_model_data(file_path: &str) -> Result<ModelData, Box<dyn Error>> {
         let mut rdr = Reader::from_path(file_path)?;
-        let mut time = Vec::new();
-        let mut voltage = Vec::new();
-        let mut c1c = Vec::new();
-        let mut c0c = Vec::new();
-        let mut c1a = Vec::new();
-        let mut c2a = Vec::new();
-        
-        // Skip header row
+        let mut data = ModelData {
+            time: Vec::new(),
+            voltage: Vec::new(),
+            c1c: Vec::new(),
+            c0c: Vec::new(),
+            c1a: Vec::new(),
+            c2a: Vec::new(),
+            breakdown: vec![Vec::new(); BREAKDOWN_COLUMNS.len()],
+        };
+
+        // Columns are looked up by name, output.csv gains columns as the model grows
         let headers = rdr.headers()?.clone();
         println!("CSV headers: {:?}", headers);
-        
-        for (i, result) in rdr.records().enumerate() {
+
+        let time = column_index(&headers, "Time")?;
+        let voltage = column_index(&headers, "Simulated Voltage")?;
+        let c1c = column_index(&headers, "c1c")?;
+        let c0c = column_index(&headers, "c0c")?;
+        let c1a = column_index(&headers, "c1a")?;
+        let c2a = column_index(&headers, "c2a")?;
+        let breakdown = BREAKDOWN_COLUMNS.iter()
+            .map(|(name, _)| column_index(&headers, name))
+            .collect::<Result<Vec<usize>, _>>()?;
+
+        for result in rdr.records() {
             let record = result?;
-            
-            time.push(record[0].trim().parse()?);
-            voltage.push(record[1].trim().parse()?);
-            c1c.push(record[2].trim().parse()?);
-            c0c.push(record[3].trim().parse()?);
-            c1a.push(record[4].trim().parse()?);
-            c2a.push(record[5].trim().parse()?);
+
+            data.time.push(record[time].trim().parse()?);
+            data.voltage.push(record[voltage].trim().parse()?);
+            data.c1c.push(record[c1c].trim().parse()?);
+            data.c0c.push(record[c0c].trim().parse()?);
+            data.c1a.push(record[c1a].trim().parse()?);
+            data.c2a.push(record[c2a].trim().parse()?);
+
+            // Running sum so each trace sits on top of the previous contributions
+            let mut stacked = 0.0;
+            for (series, &index) in data.breakdown.iter_mut().zip(&breakdown) {
+                stacked += record[index].trim().parse::<f64>()?;
+                series.push(stacked);
+            }
         }
 
-        println!("Successfully read {} data points", time.len());
-        Ok((time, voltage, c1c, c0c, c1a, c2a))
+        println!("Successfully read {} data points", data.time.len());
+        Ok(data)
     }
 
     fn read_experimental_data(file_path: &str) -> Result<(Vec<f64>, Vec<f64>), Box<dyn Error>> {
@@ -89,15 +139,16 @@ impl PlotViewer {
 
     fn refresh_data(&mut self) -> Result<(), Box<dyn Error>> {
         println!("Refreshing data...");
-        let (time, voltage, c1c, c0c, c1a, c2a) = Self::read_model_data("output.csv")?;
+        let model = Self::read_model_data("output.csv")?;
         let (exp_time, exp_voltage) = Self::read_experimental_data("data.csv")?;
 
-        self.time_data = time;
-        self.voltage_data = voltage;
-        self.c1a_data = c1a;
-        self.c2a_data = c2a;
-        self.c1c_data = c1c;
-        self.c0c_data = c0c;
+        self.time_data = model.time;
+        self.voltage_data = model.voltage;
+        self.c1a_data = model.c1a;
+        self.c2a_data = model.c2a;
+        self.c1c_data = model.c1c;
+        self.c0c_data = model.c0c;
+        self.breakdown_data = model.breakdown;
         self.exp_time = exp_time;
         self.exp_voltage = exp_voltage;
 
@@ -112,6 +163,7 @@ impl eframe::App for PlotViewer {
             ui.horizontal(|ui| {
                 ui.checkbox(&mut self.show_voltage, "Voltage");
                 ui.checkbox(&mut self.show_concentration, "Concentration");
+                ui.checkbox(&mut self.show_breakdown, "Voltage Breakdown");
                 ui.checkbox(&mut self.show_experimental, "Experimental Data");
                 
                 // Add refresh button
@@ -123,7 +175,8 @@ impl eframe::App for PlotViewer {
             });
 
             let available_height = ui.available_height();
-            let plot_height = (available_height - 40.0) / 2.0; // Account for padding
+            let plot_count = [self.show_voltage, self.show_concentration, self.show_breakdown].iter().filter(|&&shown| shown).count().max(1);
+            let plot_height = (available_height - 20.0 * plot_count as f32) / plot_count as f32; // Account for padding
 
             ui.vertical(|ui| {
                 // Voltage plot
@@ -215,6 +268,38 @@ impl eframe::App for PlotViewer {
                             });
                     });
                 }
+
+                // Voltage breakdown plot, the last trace is the simulated voltage
+                if self.show_breakdown {
+                    ui.group(|ui| {
+                        Plot::new("breakdown_plot")
+                            .height(plot_height)
+                            .width(ui.available_width())  // Make plot fill available width
+                            .legend(egui_plot::Legend::default())  // Add legend
+                            .auto_bounds_x()  // Automatically fit x bounds
+                            .auto_bounds_y()  // Automatically fit y bounds
+                            .show(ui, |plot_ui| {
+                                let colors = [
+                                    egui::Color32::GRAY,
+                                    egui::Color32::LIGHT_BLUE,
+                                    egui::Color32::GREEN,
+                                    egui::Color32::YELLOW,
+                                    egui::Color32::RED,
+                                ];
+
+                                for ((series, (_, name)), color) in self.breakdown_data.iter().zip(BREAKDOWN_COLUMNS).zip(colors) {
+                                    let points: Vec<[f64; 2]> = self.time_data.iter()
+                                        .zip(series)
+                                        .map(|(&t, &v)| [t, v])
+                                        .collect();
+                                    plot_ui.line(Line::new(PlotPoints::new(points))
+                                        .name(name)
+                                        .width(2.0)
+                                        .color(color));
+                                }
+                            });
+                    });
+                }
             });
         });
     }
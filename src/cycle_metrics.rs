@@ -0,0 +1,220 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/// Charge put in and taken out during one cycle (Ah)
+pub struct CycleCapacity {
+    pub cycle: u32,
+    pub charge_capacity: f32,
+    pub discharge_capacity: f32,
+}
+
+/// Charge and discharge capacity of every cycle, each sample's current is held up to the next sample
+pub fn capacity_per_cycle(time: &[f32], current: &[f32], cycle: &[u32]) -> Vec<CycleCapacity> {
+    let mut capacities: Vec<CycleCapacity> = Vec::new();
+
+    for index in 0..current.len() {
+        let interval = sample_interval(time, index);
+
+        if capacities.last().is_none_or(|capacity| capacity.cycle != cycle[index]) {
+            capacities.push(CycleCapacity { cycle: cycle[index], charge_capacity: 0.0, discharge_capacity: 0.0 });
+        }
+        let capacity = capacities.last_mut().unwrap();
+
+        if current[index] > 0.0 {
+            capacity.charge_capacity += current[index] * interval / 3600.0;
+        } else {
+            capacity.discharge_capacity -= current[index] * interval / 3600.0;
+        }
+    }
+
+    capacities
+}
+
+/// Charge, energy and efficiencies of one cycle, voltages for the whole stack
+pub struct CycleMetrics {
+    pub cycle: u32,
+    pub charge_capacity: f32,
+    pub discharge_capacity: f32,
+    pub charge_energy: f32,
+    pub discharge_energy: f32,
+    /// Charge capacity the electrolyte can store (Ah), the reference for the utilisation
+    pub theoretical_capacity: f32,
+}
+
+impl CycleMetrics {
+    pub fn coulombic_efficiency(&self) -> f32 {
+        percentage(self.discharge_capacity, self.charge_capacity)
+    }
+
+    /// Mean discharge voltage over mean charge voltage
+    pub fn voltage_efficiency(&self) -> f32 {
+        percentage(self.discharge_energy / self.discharge_capacity, self.charge_energy / self.charge_capacity)
+    }
+
+    pub fn energy_efficiency(&self) -> f32 {
+        percentage(self.discharge_energy, self.charge_energy)
+    }
+
+    /// Share of the theoretical capacity delivered on discharge
+    pub fn utilisation(&self) -> f32 {
+        percentage(self.discharge_capacity, self.theoretical_capacity)
+    }
+}
+
+/// Capacity (Ah), energy (Wh) and efficiencies of every cycle, each sample's current and voltage are held up to the next sample
+pub fn cycle_metrics(time: &[f32], current: &[f32], voltage: &[f32], cycle: &[u32], theoretical_capacity: f32) -> Vec<CycleMetrics> {
+    let mut metrics: Vec<CycleMetrics> = Vec::new();
+
+    for index in 0..current.len() {
+        let interval = sample_interval(time, index);
+
+        if metrics.last().is_none_or(|metric| metric.cycle != cycle[index]) {
+            metrics.push(CycleMetrics {
+                cycle: cycle[index],
+                charge_capacity: 0.0,
+                discharge_capacity: 0.0,
+                charge_energy: 0.0,
+                discharge_energy: 0.0,
+                theoretical_capacity,
+            });
+        }
+        let metric = metrics.last_mut().unwrap();
+
+        let charge = current[index] * interval / 3600.0;
+        if current[index] > 0.0 {
+            metric.charge_capacity += charge;
+            metric.charge_energy += charge * voltage[index];
+        } else {
+            metric.discharge_capacity -= charge;
+            metric.discharge_energy -= charge * voltage[index];
+        }
+    }
+
+    metrics
+}
+
+fn percentage(numerator: f32, denominator: f32) -> f32 {
+    // Undefined for cycles without charge or discharge
+    if denominator > 0.0 {
+        numerator / denominator * 100.0
+    } else {
+        f32::NAN
+    }
+}
+
+fn sample_interval(time: &[f32], index: usize) -> f32 {
+    // The last sample has no successor, assume the same spacing as the previous one
+    if index + 1 < time.len() {
+        time[index + 1] - time[index]
+    } else if index > 0 {
+        time[index] - time[index - 1]
+    } else {
+        0.0
+    }
+}
+
+/// Write the per-cycle capacities with the retention relative to the first cycle's discharge
+pub fn write_capacity_report(path: &str, capacities: &[CycleCapacity]) -> Result<(), Box<dyn Error>> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "Cycle, Charge capacity (Ah), Discharge capacity (Ah), Retention (%)")?;
+
+    let reference = capacities.iter()
+        .map(|capacity| capacity.discharge_capacity)
+        .find(|&discharge| discharge > 0.0)
+        .unwrap_or(1.0);
+
+    for capacity in capacities {
+        writeln!(writer, "{}, {}, {}, {}",
+            capacity.cycle, capacity.charge_capacity, capacity.discharge_capacity,
+            capacity.discharge_capacity / reference * 100.0)?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write the per-cycle metrics of the simulation and, when known, of the measured data
+pub fn write_cycle_summary(path: &str, simulated: &[CycleMetrics], measured: Option<&[CycleMetrics]>) -> Result<(), Box<dyn Error>> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "Cycle, Source, Charge capacity (Ah), Discharge capacity (Ah), Charge energy (Wh), Discharge energy (Wh), Coulombic efficiency (%), Voltage efficiency (%), Energy efficiency (%), Utilisation (%)")?;
+
+    let sources = [("Simulated", Some(simulated)), ("Measured", measured)];
+
+    for (index, simulated_metric) in simulated.iter().enumerate() {
+        for (source, metrics) in sources {
+            let Some(metric) = metrics.and_then(|metrics| metrics.get(index)) else {
+                continue;
+            };
+
+            writeln!(writer, "{}, {}, {}, {}, {}, {}, {}, {}, {}, {}",
+                simulated_metric.cycle, source, metric.charge_capacity, metric.discharge_capacity,
+                metric.charge_energy, metric.discharge_energy, metric.coulombic_efficiency(),
+                metric.voltage_efficiency(), metric.energy_efficiency(), metric.utilisation())?;
+        }
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(value: f32, expected: f32) {
+        assert!((value - expected).abs() < 1.0e-3 * expected.abs(), "{} against {}", value, expected);
+    }
+
+    /// Two cycles sampled every 10 s, each an hour of 10 A charge at 1.5 V then an hour of 9 A discharge at 1.2 V
+    fn constant_current_trace() -> (Vec<f32>, Vec<f32>, Vec<f32>, Vec<u32>) {
+        let (mut time, mut current, mut voltage, mut cycle) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+
+        for cycle_number in 1..=2 {
+            for (step_current, step_voltage) in [(10.0, 1.5), (-9.0, 1.2)] {
+                for _ in 0..360 {
+                    time.push(10.0 * (time.len() + 1) as f32);
+                    current.push(step_current);
+                    voltage.push(step_voltage);
+                    cycle.push(cycle_number);
+                }
+            }
+        }
+
+        (time, current, voltage, cycle)
+    }
+
+    #[test]
+    fn constant_current_cycles() {
+        let (time, current, voltage, cycle) = constant_current_trace();
+        let metrics = cycle_metrics(&time, &current, &voltage, &cycle, 20.0);
+
+        assert_eq!(metrics.len(), 2);
+        for (metric, cycle_number) in metrics.iter().zip(1..) {
+            assert_eq!(metric.cycle, cycle_number);
+            assert_close(metric.charge_capacity, 10.0);
+            assert_close(metric.discharge_capacity, 9.0);
+            assert_close(metric.charge_energy, 15.0);
+            assert_close(metric.discharge_energy, 10.8);
+            assert_close(metric.coulombic_efficiency(), 90.0);
+            assert_close(metric.voltage_efficiency(), 80.0);
+            assert_close(metric.energy_efficiency(), 72.0);
+            assert_close(metric.utilisation(), 45.0);
+        }
+
+        let capacities = capacity_per_cycle(&time, &current, &cycle);
+        for (capacity, metric) in capacities.iter().zip(&metrics) {
+            assert_eq!(capacity.cycle, metric.cycle);
+            assert_close(capacity.charge_capacity, metric.charge_capacity);
+            assert_close(capacity.discharge_capacity, metric.discharge_capacity);
+        }
+    }
+
+    #[test]
+    fn efficiencies_are_undefined_without_charge() {
+        let metrics = cycle_metrics(&[10.0, 20.0], &[-5.0, -5.0], &[1.2, 1.2], &[1, 1], 20.0);
+
+        assert!(metrics[0].coulombic_efficiency().is_nan());
+        assert!(metrics[0].energy_efficiency().is_nan());
+    }
+}
@@ -38,7 +38,7 @@ pub fn process_data(input_path: &str, output_path: &str) -> Result<(), Box<dyn E
     
     for (time, voltage, current) in data {
         let bin = time / 60; // 60-second bins
-        binned_data.entry(bin).or_insert_with(Vec::new).push((voltage, current));
+        binned_data.entry(bin).or_default().push((voltage, current));
     }
 
     // Calculate averages for each bin and sort by time
@@ -55,7 +55,7 @@ pub fn process_data(input_path: &str, output_path: &str) -> Result<(), Box<dyn E
 
     // Write to output CSV
     let mut wtr = Writer::from_path(output_path)?;
-    wtr.write_record(&["Time (s)", "Voltage", "Current"])?;
+    wtr.write_record(["Time (s)", "Voltage", "Current"])?;
 
     for (time, voltage, current) in final_data {
         wtr.write_record(&[
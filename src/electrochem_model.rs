@@ -1,223 +1,1427 @@
 use std::io::BufWriter;
 use std::io::Write;                                                                                                                                                                                                                                                                                                                           
 use std::fs::File; 
+use std::path::Path;
 
-const ELECTROLYTE_VOLUME: f32 = 0.06; 
-const TEMPERATURE: f32 = 333.15;
-const CELLS: f32 = 30.0;
+use serde::{Deserialize, Serialize};
+
+use crate::cycle_metrics::{capacity_per_cycle, cycle_metrics, write_capacity_report, write_cycle_summary};
+use crate::impedance::Complex;
+use crate::integrator::{integrate, Integrator, OdeSystem};
+use crate::model_parameters::ModelParameters;
+use crate::shunt::{shunt_currents, shunt_loss};
+use crate::speciation::ChlorideSpeciation;
+use crate::stack_config::{CellFactors, ShuntConfig, StackConfig};
+
+const WATER_MOLAR_VOLUME: f32 = 1.8e-5;
+const ELECTROLYTE_DENSITY: f32 = 1300.0;
+const ELECTROLYTE_HEAT_CAPACITY: f32 = 3200.0;
 
 const FARADAY_CONSTANT: f32 = 96485.0;
 const FORMAL_POTENTIAL: f32 = 0.65;
 const GAS_CONSTANT: f32 = 8.3145;
 const COPPER_UNITY: f32 = 1000.0;
 const Z_ELECTRON: f32 = 1.0;
+const LIMITING_CURRENT_FRACTION: f32 = 0.999;
+const SOLVER_INITIAL_CURRENT: f32 = 1.0;
+const SOLVER_MAXIMUM_CURRENT: f32 = 1000.0;
+const SOLVER_ITERATIONS: usize = 40;
+const MINIMUM_TANK_FRACTION: f32 = 0.02;
+const COPPER_FULL_COVERAGE_LOADING: f32 = 0.1;
+const MINIMUM_COPPER_COVERAGE: f32 = 1.0e-3;
+const SOLUTION_DIFFUSION_COEFFICIENT: f32 = 7.0e-10;
 
+// Integrated state of each cell, in the order of ElectrochemicalModel::state
+const CELL_STATES: usize = 6;
+const ANOLYTE_C1: usize = 0;
+const ANOLYTE_C2: usize = 1;
+const CATHOLYTE_C1: usize = 2;
+const COPPER_LOADING: usize = 3;
+const POSITIVE_OVERPOTENTIAL: usize = 4;
+const NEGATIVE_OVERPOTENTIAL: usize = 5;
+
+/// Optional physics that can be switched on or off per simulation, read from the options block of stack.json, all off by default
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModelOptions {
+    /// Scale the negative exchange current with the fraction of the electrode covered by copper
+    pub copper_surface_effect: bool,
+    /// Hydrogen evolution on the negative and chlorine/oxygen evolution on the positive during charge
+    pub side_reactions: bool,
+    /// Use free ion activities from chloride complexation instead of total copper concentrations
+    pub speciation: Option<ChlorideSpeciation>,
+    /// Copper loss, electrolyte volume imbalance and stack resistance growth over many cycles
+    pub degradation: bool,
+    /// Double-layer charging on each electrode, splits the cell current into faradaic and capacitive parts
+    pub double_layer: bool,
+}
+
+
+/// How the stack is driven during a time step, currents and powers are positive on charge
+#[derive(Clone, Copy)]
+#[allow(clippy::enum_variant_names)]
+pub enum OperatingMode {
+    /// Prescribed stack current (A)
+    ConstantCurrent(f32),
+    /// Current solved each step so the stack voltage matches the setpoint (V)
+    ConstantVoltage(f32),
+    /// Current solved each step so the stack power I·V matches the target (W)
+    ConstantPower(f32),
+    /// Constant current until the voltage limit is reached, then held at the limit until the current tapers to the cut-off
+    ConstantCurrentConstantVoltage { current: f32, voltage_limit: f32, current_cutoff: f32 },
+}
 
-#[allow(dead_code)]
-struct ElectrochemicalModel {
+#[derive(Clone)]
+pub struct ElectrochemicalModel {
     diffusion_number: f32,
     rate_constant_positive: f32,
     rate_constant_negative: f32,
+    mass_transfer_factor: f32,
+
+    cell_count: f32,
+    electrode_width: f32,
+    electrode_thickness: f32,
 
     membrane_surface_area: f32,
     membrane_thickness: f32,
     stack_resistance: f32,
+    // Step in use and the configured step, the longest allowed (s)
     time_step: f32,
+    nominal_time_step: f32,
 
-    anolyte_c1: f32,
-    anolyte_c2: f32,
+    // Electrolyte hold-up of one half-cell and of the external tanks (m³)
+    half_cell_volume: f32,
+    anolyte_tank_volume: f32,
+    catholyte_tank_volume: f32,
+    // Tank volumes below which nothing more is drawn from the tank, a fraction of the starting volumes (m³)
+    minimum_anolyte_tank_volume: f32,
+    minimum_catholyte_tank_volume: f32,
 
-    catholyte_c1: f32,
-    catholyte_c0: f32,
+    // Volumetric flow rate through the stack on each side (m³/s)
+    flow_rate: f32,
+
+    // Electrolyte and copper inside the stack, where the reactions take place
+    cells: Vec<Cell>,
+    copper_surface_effect: bool,
+
+    // Concentrations in the well-mixed tanks
+    anolyte_tank_c1: f32,
+    anolyte_tank_c2: f32,
+
+    catholyte_tank_c1: f32,
+
+    speciation: Option<ChlorideSpeciation>,
 
     current_i: f32,
 
+    // Shunt currents through the manifolds, dissipated power (W) and charge lost per cell (C)
+    shunt: Option<ShuntConfig>,
+    shunt_loss: f32,
+    shunt_charge: f32,
+
+    // Parasitic side reactions during charge following Tafel kinetics, exchange currents (A/m²) and slopes (V/decade)
+    side_reactions: bool,
+    her_exchange_current: f32,
+    her_tafel_slope: f32,
+    oer_exchange_current: f32,
+    oer_tafel_slope: f32,
+
+    // Charge accounting per cell (C)
+    charge_passed: f32,
+    her_charge: f32,
+    oer_charge: f32,
+
+    // Long-term degradation, copper loss (1/s), electrolyte crossover (m³/Ah) and resistance growth (Ohm/Ah)
+    degradation: bool,
+    copper_loss_rate: f32,
+    electrolyte_crossover: f32,
+    resistance_growth: f32,
+    initial_stack_resistance: f32,
+
+    // Water transport across the membrane, drag (mol water per mol charge) and osmotic permeability (m/s per mol/m³)
+    electro_osmotic_drag: f32,
+    osmotic_permeability: f32,
+
+    // Double-layer capacitance of each electrode (F/m²)
+    double_layer: bool,
+    positive_capacitance: f32,
+    negative_capacitance: f32,
+
+    // Lumped stack and tank temperature (K) and its energy balance
+    temperature: f32,
+    ambient_temperature: f32,
+    heat_transfer_coefficient: f32,
+
+    integrator: Integrator,
+
     voltage: f32,
     cycle: f32,
+    last_direction: f32,
 
     charge_offset: f32,
     discharge_offset:f32
 }
 
+/// State of one cell of the stack, or of a group of identical cells
+#[derive(Clone, Copy)]
+struct Cell {
+    // Number of cells represented, the whole stack when there is no cell-to-cell variation
+    count: f32,
+    factors: CellFactors,
+
+    // Concentrations inside the cell
+    anolyte_c1: f32,
+    anolyte_c2: f32,
+
+    catholyte_c1: f32,
+
+    // Plated copper on the negative electrode (mol/m²)
+    copper_loading: f32,
+
+    // Activation overpotential held by each double layer (V)
+    positive_overpotential: f32,
+    negative_overpotential: f32,
+
+    // Side reaction and faradaic currents from the last voltage calculation
+    her_current: f32,
+    oer_current: f32,
+    positive_faradaic: f32,
+    negative_faradaic: f32,
+
+    // Current through the cell, the stack current less the shunt currents
+    current: f32,
+    voltage: f32,
+}
+
+/// Contributions to one cell's voltage (V)
+#[derive(Clone, Copy)]
+struct Polarisation {
+    // Standard potential and Nernst term
+    open_circuit: f32,
+    positive_activation: f32,
+    negative_activation: f32,
+    positive_concentration: f32,
+    negative_concentration: f32,
+    ohmic: f32,
+    offset: f32,
+    // Current crossing each electrode interface, the cell current less the double-layer charging (A)
+    positive_faradaic: f32,
+    negative_faradaic: f32,
+}
+
+impl Polarisation {
+    fn voltage(&self) -> f32 {
+        let butler_volmer_overpotential = self.positive_activation - self.negative_activation;
+        let concentration_overpotential = self.positive_concentration - self.negative_concentration;
+
+        butler_volmer_overpotential + concentration_overpotential + self.open_circuit + self.offset + self.ohmic
+    }
+
+    /// Polarisation of each electrode, drives the side reactions
+    fn positive_electrode(&self) -> f32 {
+        self.positive_activation.abs() + self.positive_concentration.abs()
+    }
+
+    fn negative_electrode(&self) -> f32 {
+        self.negative_activation.abs() + self.negative_concentration.abs()
+    }
+
+    fn heat_generation(&self, current: f32) -> f32 {
+        // Irreversible heat, activation and mass-transfer losses on both electrodes and ohmic heating
+        let concentration_overpotential = self.positive_concentration - self.negative_concentration;
+
+        (self.positive_faradaic * self.positive_activation).abs() + (self.negative_faradaic * self.negative_activation).abs()
+            + current.abs() * concentration_overpotential.abs() + self.ohmic * current
+    }
+}
+
+/// Stack voltage split into its contributions (V), each summed over the cells
+#[derive(Clone, Copy, Default)]
+pub struct VoltageBreakdown {
+    pub open_circuit: f32,
+    pub activation: f32,
+    pub concentration: f32,
+    pub ohmic: f32,
+    pub offset: f32,
+}
+
+impl VoltageBreakdown {
+    pub fn voltage(&self) -> f32 {
+        self.open_circuit + self.activation + self.concentration + self.ohmic + self.offset
+    }
+}
+
+/// Rates of change of the integrated state (per second), laid out like ElectrochemicalModel::state
+struct StateRates {
+    cells: Vec<[f32; CELL_STATES]>,
+    anolyte_tank_c1: f32,
+    anolyte_tank_c2: f32,
+    catholyte_tank_c1: f32,
+    anolyte_tank_volume: f32,
+    catholyte_tank_volume: f32,
+    temperature: f32,
+    charge_passed: f32,
+    her_charge: f32,
+    oer_charge: f32,
+    shunt_charge: f32,
+}
+
+impl StateRates {
+    fn new(cells: usize) -> Self {
+        StateRates {
+            cells: vec![[0.0; CELL_STATES]; cells],
+            anolyte_tank_c1: 0.0,
+            anolyte_tank_c2: 0.0,
+            catholyte_tank_c1: 0.0,
+            anolyte_tank_volume: 0.0,
+            catholyte_tank_volume: 0.0,
+            temperature: 0.0,
+            charge_passed: 0.0,
+            her_charge: 0.0,
+            oer_charge: 0.0,
+            shunt_charge: 0.0,
+        }
+    }
+
+    fn to_vec(&self) -> Vec<f64> {
+        let mut rates: Vec<f64> = self.cells.iter().flatten().map(|&rate| rate as f64).collect();
+        rates.extend([
+            self.anolyte_tank_c1, self.anolyte_tank_c2, self.catholyte_tank_c1,
+            self.anolyte_tank_volume, self.catholyte_tank_volume, self.temperature,
+            self.charge_passed, self.her_charge, self.oer_charge, self.shunt_charge,
+        ].map(|rate| rate as f64));
+
+        rates
+    }
+}
+
 impl ElectrochemicalModel {
+    pub fn new(parameters: &ModelParameters, options: &ModelOptions, stack: &StackConfig) -> Self {
+        let cell_count = stack.cells as f32;
+        let stack_volume = stack.half_cell_volume() * cell_count;
+
+        let nominal_cell = Cell {
+            count: 1.0,
+            factors: CellFactors::default(),
+
+            anolyte_c1: parameters.anolyte_c1 as f32,
+            anolyte_c2: parameters.anolyte_c2 as f32,
+
+            catholyte_c1: parameters.catholyte_c1 as f32,
+
+            // C0c is given as copper per catholyte volume, spread over the negative electrodes
+            copper_loading: parameters.catholyte_c0 as f32 * stack.catholyte_volume / (stack.membrane_surface_area() * cell_count),
+
+            positive_overpotential: 0.0,
+            negative_overpotential: 0.0,
+
+            her_current: 0.0,
+            oer_current: 0.0,
+            positive_faradaic: 0.0,
+            negative_faradaic: 0.0,
+
+            current: 0.0,
+            voltage: 0.0,
+        };
+
+        let cells = match (stack.cell_factors(), stack.shunt) {
+            (Some(factors), _) => factors.into_iter().map(|factors| Cell { factors, ..nominal_cell }).collect(),
+            // Shunt currents depend on each cell's position in the stack
+            (None, Some(_)) => vec![nominal_cell; stack.cells as usize],
+            // Identical cells are simulated once and counted for the whole stack
+            (None, None) => vec![Cell { count: cell_count, ..nominal_cell }],
+        };
+
+        ElectrochemicalModel {
+            diffusion_number: parameters.diffusion_coefficient as f32, 
+            rate_constant_positive: parameters.rate_constant_positive as f32,
+            rate_constant_negative: parameters.rate_constant_negative as f32,
+            mass_transfer_factor: parameters.mass_transfer_factor as f32,
+
+            cell_count,
+            electrode_width: stack.membrane_width,
+            electrode_thickness: stack.electrode_thickness,
+
+            membrane_surface_area: stack.membrane_surface_area(),
+            membrane_thickness: stack.membrane_thickness,
+            stack_resistance: parameters.stack_resistance as f32,
+            time_step: stack.time_step,
+            nominal_time_step: stack.time_step,
+
+            half_cell_volume: stack.half_cell_volume(),
+            anolyte_tank_volume: stack.anolyte_volume - stack_volume,
+            catholyte_tank_volume: stack.catholyte_volume - stack_volume,
+            minimum_anolyte_tank_volume: MINIMUM_TANK_FRACTION * (stack.anolyte_volume - stack_volume),
+            minimum_catholyte_tank_volume: MINIMUM_TANK_FRACTION * (stack.catholyte_volume - stack_volume),
+
+            flow_rate: stack.flow_rate,
+
+            cells,
+            copper_surface_effect: options.copper_surface_effect,
+
+            // Tanks start in equilibrium with the stack
+            anolyte_tank_c1: parameters.anolyte_c1 as f32,
+            anolyte_tank_c2: parameters.anolyte_c2 as f32,
+
+            catholyte_tank_c1: parameters.catholyte_c1 as f32,
+
+            speciation: options.speciation,
+
+            current_i: 32.0,
+
+            shunt: stack.shunt,
+            shunt_loss: 0.0,
+            shunt_charge: 0.0,
+
+            side_reactions: options.side_reactions,
+            her_exchange_current: parameters.her_exchange_current as f32,
+            her_tafel_slope: parameters.her_tafel_slope as f32,
+            oer_exchange_current: parameters.oer_exchange_current as f32,
+            oer_tafel_slope: parameters.oer_tafel_slope as f32,
+
+            charge_passed: 0.0,
+            her_charge: 0.0,
+            oer_charge: 0.0,
+
+            degradation: options.degradation,
+            copper_loss_rate: parameters.copper_loss_rate as f32,
+            electrolyte_crossover: parameters.electrolyte_crossover as f32,
+            resistance_growth: parameters.resistance_growth as f32,
+            initial_stack_resistance: parameters.stack_resistance as f32,
+
+            electro_osmotic_drag: parameters.electro_osmotic_drag as f32,
+            osmotic_permeability: parameters.osmotic_permeability as f32,
+
+            double_layer: options.double_layer,
+            positive_capacitance: parameters.positive_capacitance as f32,
+            negative_capacitance: parameters.negative_capacitance as f32,
+
+            // Start-up from ambient
+            temperature: stack.ambient_temperature,
+            ambient_temperature: stack.ambient_temperature,
+            heat_transfer_coefficient: stack.heat_transfer_coefficient,
+
+            integrator: stack.integrator,
+
+            voltage: 0.0,
+            cycle: 0.0,
+            last_direction: 0.0,
+
+            charge_offset: parameters.charge_offset as f32,
+            discharge_offset: parameters.discharge_offset as f32
+        }
+    }
+
+    /// Run one time step at the given stack current
+    pub fn advance(&mut self, current: f32) {
+        self.advance_interval(current, self.nominal_time_step);
+    }
+
+    /// Hold the stack current over an interval (s), split into equal steps no longer than the configured time step
+    pub fn advance_interval(&mut self, current: f32, interval: f32) {
+        let steps = self.split_interval(interval);
+
+        for _ in 0..steps {
+            self.current_i = current;
+            self.time_step();
+        }
+    }
+
+    fn split_interval(&mut self, interval: f32) -> usize {
+        // Sets the step for the interval and returns the number of steps
+        let steps = (interval / self.nominal_time_step).ceil().max(1.0);
+        self.time_step = interval / steps;
+
+        steps as usize
+    }
+
+    pub fn voltage(&self) -> f32 {
+        self.voltage
+    }
+
+    pub fn current(&self) -> f32 {
+        self.current_i
+    }
+
+    pub fn temperature(&self) -> f32 {
+        self.temperature
+    }
+
+    pub fn time_step_size(&self) -> f32 {
+        self.nominal_time_step
+    }
+
+    /// Voltage of every cell from the first to the last, summing to the stack voltage
+    pub fn cell_voltages(&self) -> Vec<f32> {
+        self.cells.iter()
+            .flat_map(|cell| std::iter::repeat_n(cell.voltage, cell.count as usize))
+            .collect()
+    }
+
+    /// Contributions to the present stack voltage, summing to voltage()
+    pub fn voltage_breakdown(&self) -> VoltageBreakdown {
+        let mass_transfer_coefficient = self.mass_transfer_coefficient();
+        let mut breakdown = VoltageBreakdown::default();
+
+        for cell in &self.cells {
+            let polarisation = self.polarisation(cell, mass_transfer_coefficient);
+
+            breakdown.open_circuit += cell.count * polarisation.open_circuit;
+            breakdown.activation += cell.count * (polarisation.positive_activation - polarisation.negative_activation);
+            breakdown.concentration += cell.count * (polarisation.positive_concentration - polarisation.negative_concentration);
+            breakdown.ohmic += cell.count * polarisation.ohmic;
+            breakdown.offset += cell.count * polarisation.offset;
+        }
+
+        breakdown
+    }
+
+    /// Put the electrolyte at rest at the given state of charge and hold the temperature and current.
+    /// C2 is set on the positive side and the negative side follows by charge balance, stack and tanks equal.
+    pub fn set_operating_point(&mut self, state_of_charge: f32, temperature: f32, current: f32) {
+        let anolyte_total = self.anolyte_tank_c1 + self.anolyte_tank_c2;
+        let anolyte_volume = self.stack_volume() + self.anolyte_tank_volume;
+        let catholyte_volume = self.stack_volume() + self.catholyte_tank_volume;
+
+        // C2 formed on the positive side matches the copper plated on the negative side
+        let converted = (state_of_charge.clamp(0.0, 1.0) * anolyte_total - self.anolyte_tank_c2) * anolyte_volume;
+
+        self.anolyte_tank_c2 += converted / anolyte_volume;
+        self.anolyte_tank_c1 -= converted / anolyte_volume;
+        self.catholyte_tank_c1 = (self.catholyte_tank_c1 - converted / catholyte_volume).max(0.0);
+
+        for cell in &mut self.cells {
+            cell.anolyte_c1 = self.anolyte_tank_c1;
+            cell.anolyte_c2 = self.anolyte_tank_c2;
+            cell.catholyte_c1 = self.catholyte_tank_c1;
+            cell.copper_loading = (cell.copper_loading + converted / (self.membrane_surface_area * self.cell_count)).max(0.0);
+            cell.current = current;
+        }
+
+        self.temperature = temperature;
+        self.current_i = current;
+
+        // Double layers charged to the steady-state overpotentials
+        let mass_transfer_coefficient = self.mass_transfer_coefficient();
+        let double_layer = self.double_layer;
+        self.double_layer = false;
+        for index in 0..self.cells.len() {
+            let polarisation = self.polarisation(&self.cells[index], mass_transfer_coefficient);
+            self.cells[index].positive_overpotential = polarisation.positive_activation;
+            self.cells[index].negative_overpotential = polarisation.negative_activation;
+        }
+        self.double_layer = double_layer;
+
+        self.voltage_calc();
+    }
+
+    /// Small-signal stack impedance (Ohm) at the frequency (Hz) around the present state, shunt paths ignored
+    pub fn impedance(&self, frequency: f64) -> Complex {
+        let angular_frequency = 2.0 * std::f64::consts::PI * frequency;
+        let mass_transfer_coefficient = self.mass_transfer_coefficient();
+
+        // Finite-length diffusion across the Nernst layer δ = D / km, time constant δ² / D
+        let diffusion_time = (SOLUTION_DIFFUSION_COEFFICIENT / mass_transfer_coefficient.powi(2)) as f64;
+        let diffusion_argument = Complex::new(0.0, angular_frequency * diffusion_time).sqrt();
+        let warburg_shape = if angular_frequency > 0.0 { diffusion_argument.tanh() / diffusion_argument } else { Complex::real(1.0) };
+
+        let mut stack_impedance = Complex::real(0.0);
+
+        for cell in &self.cells {
+            let (jp, jn) = self.exchange_current_densities(cell);
+
+            // Slope of Equations 9 and 10, d(2RT/F asinh(I / 2 j0 A)) / dI
+            let activation_voltage = (2.0 * GAS_CONSTANT * self.temperature) / FARADAY_CONSTANT;
+            let positive_charge_transfer = activation_voltage / (cell.current.powi(2) + (2.0 * jp * self.membrane_surface_area).powi(2)).sqrt();
+            let negative_charge_transfer = activation_voltage / (cell.current.powi(2) + (2.0 * jn * self.membrane_surface_area).powi(2)).sqrt();
+
+            // Slopes of the concentration overpotentials
+            let thermal_voltage = (GAS_CONSTANT * self.temperature) / (Z_ELECTRON * FARADAY_CONSTANT);
+            let limiting_positive_c1 = limiting_current(mass_transfer_coefficient, self.membrane_surface_area, cell.anolyte_c1);
+            let limiting_positive_c2 = limiting_current(mass_transfer_coefficient, self.membrane_surface_area, cell.anolyte_c2);
+            let limiting_negative_c1 = limiting_current(mass_transfer_coefficient, self.membrane_surface_area, cell.catholyte_c1);
+
+            let positive_mass_transfer = thermal_voltage * (1.0 / (limiting_positive_c2 * (1.0 + current_fraction(cell.current, limiting_positive_c2)))
+                + 1.0 / (limiting_positive_c1 * (1.0 - current_fraction(cell.current, limiting_positive_c1))));
+            let negative_mass_transfer = thermal_voltage / (limiting_negative_c1 * (1.0 - current_fraction(cell.current, limiting_negative_c1)));
+
+            let positive_electrode = electrode_impedance(positive_charge_transfer, positive_mass_transfer, self.positive_capacitance * self.membrane_surface_area, warburg_shape, angular_frequency);
+            let negative_electrode = electrode_impedance(negative_charge_transfer, negative_mass_transfer, self.negative_capacitance * self.membrane_surface_area, warburg_shape, angular_frequency);
+
+            let ohmic = Complex::real((self.stack_resistance / self.cell_count * cell.factors.resistance) as f64);
+
+            stack_impedance = stack_impedance + (ohmic + positive_electrode + negative_electrode) * cell.count as f64;
+        }
+
+        stack_impedance
+    }
+
+    /// State of charge from the positive electrolyte in the tank, fraction of copper present as C2
+    pub fn state_of_charge(&self) -> f32 {
+        self.anolyte_tank_c2 / (self.anolyte_tank_c1 + self.anolyte_tank_c2)
+    }
+
+    /// Fraction of the positive electrolyte in stack and tank present as C2
+    pub fn positive_state_of_charge(&self) -> f32 {
+        let anolyte_c1 = self.electrolyte_moles(|cell| cell.anolyte_c1, self.anolyte_tank_c1, self.anolyte_tank_volume);
+        let anolyte_c2 = self.electrolyte_moles(|cell| cell.anolyte_c2, self.anolyte_tank_c2, self.anolyte_tank_volume);
+
+        anolyte_c2 / (anolyte_c1 + anolyte_c2)
+    }
+
+    /// Fraction of the negative side's copper plated on the electrodes
+    pub fn negative_state_of_charge(&self) -> f32 {
+        let catholyte_c1 = self.electrolyte_moles(|cell| cell.catholyte_c1, self.catholyte_tank_c1, self.catholyte_tank_volume);
+
+        self.plated_copper() / (self.plated_copper() + catholyte_c1)
+    }
+
+    /// Charge the electrolyte can store per cell (Ah), set by the half-cell holding the least copper
+    pub fn theoretical_capacity(&self) -> f32 {
+        let anolyte_copper = self.electrolyte_moles(|cell| cell.anolyte_c1 + cell.anolyte_c2, self.anolyte_tank_c1 + self.anolyte_tank_c2, self.anolyte_tank_volume);
+        let catholyte_copper = self.electrolyte_moles(|cell| cell.catholyte_c1, self.catholyte_tank_c1, self.catholyte_tank_volume) + self.plated_copper();
+
+        anolyte_copper.min(catholyte_copper) * Z_ELECTRON * FARADAY_CONSTANT / 3600.0 / self.cell_count
+    }
+
     fn time_step (&mut self) {
 
         self.charge_discharge_check(); 
-        self.current_component();
-        self.diffusion_step();
+        self.shunt_step();
+        self.depletion_limit();
+        integrate(self, self.integrator, self.time_step as f64);
         self.voltage_calc();
     }
 
     fn charge_discharge_check(&mut self) {
-        // Check whether timestep can be performed or current sign flip needed
-        if self.current_i > 0.0 {
-            if self.anolyte_c1 < 0.0 {
-                self.anolyte_c1 = 0.0;
-                self.cycle += 1.0
-            } else if self.catholyte_c1 < 0.0 {
-                self.catholyte_c1 = 0.0;
-                self.cycle += 1.0
+        // A new cycle starts when charging resumes after a discharge, rests in between are ignored
+        if self.current_i > 0.0 && self.last_direction < 0.0 {
+            self.cycle += 1.0;
+        }
+        if self.current_i != 0.0 {
+            self.last_direction = self.current_i.signum();
+        }
+
+        // Negative concentration check
+        for cell in &mut self.cells {
+            if cell.anolyte_c1 < 0.0 {
+                cell.anolyte_c1 = 0.0;
+            }
+            if cell.anolyte_c2 < 0.0 {
+                cell.anolyte_c2 = 0.0;
+            }
+            if cell.copper_loading < 0.0 {
+                cell.copper_loading = 0.0;
             }
+            if cell.catholyte_c1 < 0.0 {
+                cell.catholyte_c1 = 0.0;
+            }
+        }
+    }
+
+    fn shunt_step(&mut self) {
+        let Some(shunt) = self.shunt else {
+            for cell in &mut self.cells {
+                cell.current = self.current_i;
+            }
+            return;
+        };
+
+        // Shunts driven by the cell voltages of the previous step and held over the step, the stack current enters at the positive end
+        let cell_voltages: Vec<f32> = self.cells.iter().map(|cell| cell.voltage).collect();
+        let shunt_currents = shunt_currents(&cell_voltages, &shunt);
+
+        let mut current_above = self.current_i;
+        for (cell, shunt_current) in self.cells.iter_mut().zip(&shunt_currents).rev() {
+            // Positive electrode passes the current from above, the negative one that less the shunt, the cell reacts at the mean
+            cell.current = current_above - shunt_current / 2.0;
+            current_above -= shunt_current;
+        }
+
+        self.shunt_loss = shunt_loss(&cell_voltages, &shunt_currents);
+    }
+
+    fn depletion_limit(&mut self) {
+        // Each cell can convert at most what its half-cells hold plus the net exchange of its share of the flow over the step,
+        // discharge reduces C2 and strips the plated copper, charge oxidises and plates C1
+        let flow_rate = self.flow_rate / self.cell_count;
+        let supply = |concentration: f32, tank_concentration: f32| {
+            (concentration * self.half_cell_volume + flow_rate * (tank_concentration - concentration) * self.time_step).max(0.0)
+        };
+        let convertible_current = |moles: f32| moles * Z_ELECTRON * FARADAY_CONSTANT * LIMITING_CURRENT_FRACTION / self.time_step;
+
+        let limits: Vec<(f32, f32)> = self.cells.iter()
+            .map(|cell| {
+                let copper = cell.copper_loading.max(0.0) * self.membrane_surface_area;
+                let discharge = supply(cell.anolyte_c2, self.anolyte_tank_c2).min(copper);
+                let charge = supply(cell.anolyte_c1, self.anolyte_tank_c1).min(supply(cell.catholyte_c1, self.catholyte_tank_c1));
+                (convertible_current(discharge), convertible_current(charge))
+            })
+            .collect();
+
+        // Shunts add a fixed share to every cell's current, so the most loaded cell limits the stack current
+        let limited_current = if self.current_i < 0.0 {
+            let lowest = self.cells.iter().zip(&limits)
+                .map(|(cell, (discharge, _))| self.current_i - cell.current - discharge)
+                .fold(f32::NEG_INFINITY, f32::max);
+            self.current_i.max(lowest.min(0.0))
         } else {
-            if self.anolyte_c2 < 0.0 {
-                self.anolyte_c2 = 0.0;
-                self.cycle += 1.0
-            } else if self.catholyte_c0 < 0.0 {
-               self.catholyte_c0 = 0.0;
-               self.cycle += 1.0
+            let highest = self.cells.iter().zip(&limits)
+                .map(|(cell, (_, charge))| self.current_i - cell.current + charge)
+                .fold(f32::INFINITY, f32::min);
+            self.current_i.min(highest.max(0.0))
+        };
+
+        // A cell whose shunt share alone exceeds its limit runs out, its voltage and with it the shunt current collapse
+        let reduction = limited_current - self.current_i;
+        for (cell, (discharge, charge)) in self.cells.iter_mut().zip(limits) {
+            cell.current = (cell.current + reduction).clamp(-discharge, charge);
+        }
+        self.current_i = limited_current;
+    }
+
+    fn reaction_rates(&self, polarisations: &[Polarisation], rates: &mut StateRates) {
+        for ((cell, polarisation), cell_rates) in self.cells.iter().zip(polarisations).zip(&mut rates.cells) {
+            // Part of the charging current is lost to side reactions on each electrode
+            let (her_current, oer_current) = self.side_reaction_currents(cell, polarisation);
+
+            // Moles converted per second in one cell, only the electrolyte inside the stack reacts
+            let positive_part = 1.0 / (Z_ELECTRON * FARADAY_CONSTANT) * (polarisation.positive_faradaic - oer_current);
+            let negative_part = 1.0 / (Z_ELECTRON * FARADAY_CONSTANT) * (polarisation.negative_faradaic - her_current);
+
+            cell_rates[ANOLYTE_C1] -= positive_part / self.half_cell_volume;
+            cell_rates[ANOLYTE_C2] += positive_part / self.half_cell_volume;
+
+            cell_rates[CATHOLYTE_C1] -= negative_part / self.half_cell_volume;
+            cell_rates[COPPER_LOADING] += negative_part / self.membrane_surface_area;
+
+            // The rest of the cell current charges the double layers
+            if self.double_layer {
+                cell_rates[POSITIVE_OVERPOTENTIAL] += (cell.current - polarisation.positive_faradaic) / (self.positive_capacitance * self.membrane_surface_area);
+                cell_rates[NEGATIVE_OVERPOTENTIAL] += (cell.current - polarisation.negative_faradaic) / (self.negative_capacitance * self.membrane_surface_area);
             }
+
+            // Parasitic charge averaged over the cells of the stack
+            rates.her_charge += her_current * cell.count / self.cell_count;
+            rates.oer_charge += oer_current * cell.count / self.cell_count;
+            rates.shunt_charge += (self.current_i - cell.current) * cell.count / self.cell_count;
         }
 
-        // Negative concentration check
-        if self.anolyte_c1 < 0.0 {
-            self.anolyte_c1 = 0.0;
+        rates.charge_passed += self.current_i.abs();
+    }
+
+    fn degradation_rates(&self, rates: &mut StateRates) {
+        if !self.degradation {
+            return;
         }
-        if self.anolyte_c2 < 0.0 {
-            self.anolyte_c2 = 0.0;
+
+        // Irreversible copper loss, e.g. CuCl precipitation, first order in C1 on both sides
+        for (cell, cell_rates) in self.cells.iter().zip(&mut rates.cells) {
+            cell_rates[ANOLYTE_C1] -= self.copper_loss_rate * cell.anolyte_c1;
+            cell_rates[CATHOLYTE_C1] -= self.copper_loss_rate * cell.catholyte_c1;
         }
-        if self.catholyte_c0 < 0.0 {
-            self.catholyte_c0 = 0.0;
+        rates.anolyte_tank_c1 -= self.copper_loss_rate * self.anolyte_tank_c1;
+        rates.catholyte_tank_c1 -= self.copper_loss_rate * self.catholyte_tank_c1;
+
+        // Net electrolyte crossover proportional to the current, positive from anolyte to catholyte (m³/s)
+        self.transfer_electrolyte_rates(self.electrolyte_crossover * self.current_i.abs() / 3600.0, rates);
+
+        // Stack resistance grows with cumulative throughput, follows charge_passed in set_state
+    }
+
+    fn transfer_electrolyte_rates(&self, volume_rate: f32, rates: &mut StateRates) {
+        if volume_rate > 0.0 && self.anolyte_tank_volume > self.minimum_anolyte_tank_volume {
+            // C2 arriving in the catholyte comproportionates with the deposit as in the diffusion step
+            let c2_moved = volume_rate * self.anolyte_tank_c2;
+            let plated_copper = self.plated_copper();
+            let copper_consumed = if plated_copper > 0.0 { c2_moved } else { 0.0 };
+            let c1_moved = volume_rate * self.anolyte_tank_c1 + c2_moved + copper_consumed;
+
+            rates.catholyte_tank_c1 += (c1_moved - volume_rate * self.catholyte_tank_c1) / self.catholyte_tank_volume;
+
+            // Taken from every cell in proportion to its deposit
+            for (cell, cell_rates) in self.cells.iter().zip(&mut rates.cells) {
+                if plated_copper > 0.0 {
+                    cell_rates[COPPER_LOADING] -= copper_consumed * cell.copper_loading / plated_copper;
+                }
+            }
+
+            rates.anolyte_tank_volume -= volume_rate;
+            rates.catholyte_tank_volume += volume_rate;
+        } else if volume_rate < 0.0 && self.catholyte_tank_volume > self.minimum_catholyte_tank_volume {
+            let volume_rate = -volume_rate;
+
+            rates.anolyte_tank_c1 += volume_rate * (self.catholyte_tank_c1 - self.anolyte_tank_c1) / self.anolyte_tank_volume;
+            rates.anolyte_tank_c2 -= volume_rate * self.anolyte_tank_c2 / self.anolyte_tank_volume;
+
+            rates.anolyte_tank_volume += volume_rate;
+            rates.catholyte_tank_volume -= volume_rate;
         }
-        if self.catholyte_c1 < 0.0 {
-            self.catholyte_c1 = 0.0;
+    }
+
+    fn side_reaction_currents(&self, cell: &Cell, polarisation: &Polarisation) -> (f32, f32) {
+        // Gas evolution only while charging, limited to the applied current
+        if !self.side_reactions || cell.current <= 0.0 {
+            return (0.0, 0.0);
         }
+
+        let her_current = self.her_exchange_current * self.membrane_surface_area * 10.0_f32.powf(polarisation.negative_electrode() / self.her_tafel_slope);
+        let oer_current = self.oer_exchange_current * self.membrane_surface_area * 10.0_f32.powf(polarisation.positive_electrode() / self.oer_tafel_slope);
+
+        (her_current.min(cell.current), oer_current.min(cell.current))
     }
 
-    fn current_component(&mut self) {
-        let current_part = (1.0 / (Z_ELECTRON * FARADAY_CONSTANT) * self.current_i * CELLS) * self.time_step / ELECTROLYTE_VOLUME;
+    fn diffusion_rates(&self, rates: &mut StateRates) {
+        for (cell, cell_rates) in self.cells.iter().zip(&mut rates.cells) {
+            // Molar flux per unit concentration gradient across the membrane of one cell (m³/s)
+            let membrane_thickness = self.membrane_thickness * cell.factors.membrane_thickness;
+            let diffusion_factor = self.diffusion_number * cell.factors.diffusion * (self.membrane_surface_area / membrane_thickness);
+
+            // C2 diffusion (from anolyte to catholyte)
+            let c2_gradient = cell.anolyte_c2 - 0.0; // Assuming no C2 in catholyte
+            if c2_gradient > 0.0 {
+                let c2_diffusion = diffusion_factor * c2_gradient;
 
-        self.anolyte_c1 -= current_part;
-        self.anolyte_c2 += current_part;
+                // Crossed C2 comproportionates with plated copper (C2 + Cu -> 2 C1) while any is left
+                let copper_consumed = if cell.copper_loading > 0.0 { c2_diffusion } else { 0.0 };
+
+                cell_rates[CATHOLYTE_C1] += (c2_diffusion + copper_consumed) / self.half_cell_volume;
+                cell_rates[COPPER_LOADING] -= copper_consumed / self.membrane_surface_area;
+                cell_rates[ANOLYTE_C2] -= c2_diffusion / self.half_cell_volume;
+            }
 
-        self.catholyte_c1 -= current_part;
-        self.catholyte_c0 += current_part;
+            // C1 back diffusion (from catholyte to anolyte)
+            let c1_diffusion = diffusion_factor * (cell.catholyte_c1 - cell.anolyte_c1);
+            cell_rates[CATHOLYTE_C1] -= c1_diffusion / self.half_cell_volume;
+            cell_rates[ANOLYTE_C1] += c1_diffusion / self.half_cell_volume;
+        }
     }
 
-    fn diffusion_step(&mut self) {
-        let diffusion_factor = self.diffusion_number * (self.membrane_surface_area * CELLS / self.membrane_thickness) * self.time_step / ELECTROLYTE_VOLUME;
+    fn water_transfer_rates(&self, rates: &mut StateRates) {
+        for (cell, cell_rates) in self.cells.iter().zip(&mut rates.cells) {
+            let cell_volume = self.half_cell_volume * cell.count;
+
+            // Electro-osmotic drag follows the current, anolyte to catholyte on charge
+            let drag_rate = self.electro_osmotic_drag * cell.current * cell.count / FARADAY_CONSTANT * WATER_MOLAR_VOLUME;
+
+            // Osmosis moves water towards the more concentrated side
+            let concentration_difference = cell.catholyte_c1 - (cell.anolyte_c1 + cell.anolyte_c2);
+            let osmotic_rate = self.osmotic_permeability * self.membrane_surface_area * cell.count * concentration_difference;
 
-        // C2 diffusion (from anolyte to catholyte)
-        let c2_gradient = self.anolyte_c2 - 0.0; // Assuming no C2 in catholyte
-        if c2_gradient > 0.0 {
-            let c2_diffusion = diffusion_factor * c2_gradient;
-            self.catholyte_c1 += 2.0 * c2_diffusion;
-            self.catholyte_c0 -= c2_diffusion;
-            self.anolyte_c2 -= c2_diffusion;
+            let volume_rate = drag_rate + osmotic_rate;
+
+            if volume_rate > 0.0 && self.anolyte_tank_volume > self.minimum_anolyte_tank_volume {
+                // Water leaving the anolyte side of the cell is replaced by tank electrolyte
+                cell_rates[ANOLYTE_C1] += self.anolyte_tank_c1 * volume_rate / cell_volume;
+                cell_rates[ANOLYTE_C2] += self.anolyte_tank_c2 * volume_rate / cell_volume;
+                rates.anolyte_tank_volume -= volume_rate;
+
+                // Water entering the catholyte side dilutes the cell and the displaced electrolyte returns to the tank
+                cell_rates[CATHOLYTE_C1] -= cell.catholyte_c1 * volume_rate / cell_volume;
+                rates.catholyte_tank_c1 += (cell.catholyte_c1 - self.catholyte_tank_c1) * volume_rate / self.catholyte_tank_volume;
+                rates.catholyte_tank_volume += volume_rate;
+            } else if volume_rate < 0.0 && self.catholyte_tank_volume > self.minimum_catholyte_tank_volume {
+                let volume_rate = -volume_rate;
+
+                cell_rates[CATHOLYTE_C1] += self.catholyte_tank_c1 * volume_rate / cell_volume;
+                rates.catholyte_tank_volume -= volume_rate;
+
+                cell_rates[ANOLYTE_C1] -= cell.anolyte_c1 * volume_rate / cell_volume;
+                cell_rates[ANOLYTE_C2] -= cell.anolyte_c2 * volume_rate / cell_volume;
+                rates.anolyte_tank_c1 += (cell.anolyte_c1 - self.anolyte_tank_c1) * volume_rate / self.anolyte_tank_volume;
+                rates.anolyte_tank_c2 += (cell.anolyte_c2 - self.anolyte_tank_c2) * volume_rate / self.anolyte_tank_volume;
+                rates.anolyte_tank_volume += volume_rate;
+            }
         }
+    }
 
-        // C1 back diffusion (from catholyte to anolyte)
-        let c1_gradient = self.catholyte_c1 - self.anolyte_c1;
-        if c1_gradient != 0.0 {
-            let c1_diffusion = diffusion_factor * c1_gradient;
-            self.catholyte_c1 -= c1_diffusion;
-            self.anolyte_c1 += c1_diffusion;
+    fn flow_rates(&self, rates: &mut StateRates) {
+        // Cells are fed in parallel from the manifolds, each exchanging its share of the flow with the well-mixed tanks
+        for (cell, cell_rates) in self.cells.iter().zip(&mut rates.cells) {
+            let cell_volume = self.half_cell_volume * cell.count;
+            let flow_rate = self.flow_rate * cell.count / self.cell_count;
+
+            let anolyte_c1_exchange = flow_rate * (self.anolyte_tank_c1 - cell.anolyte_c1);
+            let anolyte_c2_exchange = flow_rate * (self.anolyte_tank_c2 - cell.anolyte_c2);
+            let catholyte_c1_exchange = flow_rate * (self.catholyte_tank_c1 - cell.catholyte_c1);
+
+            cell_rates[ANOLYTE_C1] += anolyte_c1_exchange / cell_volume;
+            cell_rates[ANOLYTE_C2] += anolyte_c2_exchange / cell_volume;
+            cell_rates[CATHOLYTE_C1] += catholyte_c1_exchange / cell_volume;
+
+            rates.anolyte_tank_c1 -= anolyte_c1_exchange / self.anolyte_tank_volume;
+            rates.anolyte_tank_c2 -= anolyte_c2_exchange / self.anolyte_tank_volume;
+            rates.catholyte_tank_c1 -= catholyte_c1_exchange / self.catholyte_tank_volume;
         }
     }
 
+    fn thermal_rates(&self, polarisations: &[Polarisation], rates: &mut StateRates) {
+        // Lumped energy balance, heat generated in the stack and manifolds against losses to ambient
+        let heat_generation: f32 = self.cells.iter().zip(polarisations)
+            .map(|(cell, polarisation)| cell.count * polarisation.heat_generation(cell.current))
+            .sum::<f32>() + self.shunt_loss;
+        let heat_loss = self.heat_transfer_coefficient * (self.temperature - self.ambient_temperature);
+
+        rates.temperature += (heat_generation - heat_loss) / self.thermal_mass();
+    }
+
     fn voltage_calc(&mut self) {
-        // Butler-volmer overpotentials
+        // Cell voltages and side reaction currents at the end of the step, cells in series
+        let mass_transfer_coefficient = self.mass_transfer_coefficient();
+        let mut stack_voltage = 0.0;
+
+        for index in 0..self.cells.len() {
+            let polarisation = self.polarisation(&self.cells[index], mass_transfer_coefficient);
+            let (her_current, oer_current) = self.side_reaction_currents(&self.cells[index], &polarisation);
+
+            let cell = &mut self.cells[index];
+            cell.voltage = polarisation.voltage();
+            cell.her_current = her_current;
+            cell.oer_current = oer_current;
+            cell.positive_faradaic = polarisation.positive_faradaic;
+            cell.negative_faradaic = polarisation.negative_faradaic;
+
+            stack_voltage += cell.count * cell.voltage;
+        }
+
+        // System potenial
+        self.voltage = stack_voltage;
+    }
+
+    fn exchange_current_densities(&self, cell: &Cell) -> (f32, f32) {
+        let (anolyte_a1, anolyte_a2, catholyte_a1) = self.activities(cell);
+
         // Exchange current densities from estimated rate constant
-        let jp: f32 = 1.0 / self.membrane_surface_area * (FARADAY_CONSTANT * self.rate_constant_positive * self.anolyte_c2.powf(0.5) * self.anolyte_c1.powf(0.5));
-        let jn: f32 = 1.0 / self.membrane_surface_area * (FARADAY_CONSTANT * self.rate_constant_negative * self.catholyte_c1.powf(0.5) * COPPER_UNITY.powf(0.5));
-        
+        let jp: f32 = 1.0 / self.membrane_surface_area * (FARADAY_CONSTANT * self.rate_constant_positive * cell.factors.rate_constant_positive * anolyte_a2.powf(0.5) * anolyte_a1.powf(0.5));
+        let jn: f32 = 1.0 / self.membrane_surface_area * (FARADAY_CONSTANT * self.rate_constant_negative * cell.factors.rate_constant_negative * catholyte_a1.powf(0.5) * COPPER_UNITY.powf(0.5)) * self.copper_coverage(cell);
+
+        (jp, jn)
+    }
+
+    fn polarisation(&self, cell: &Cell, mass_transfer_coefficient: f32) -> Polarisation {
+        let (anolyte_a1, anolyte_a2, catholyte_a1) = self.activities(cell);
+
+        // Butler-volmer overpotentials
+        let (jp, jn) = self.exchange_current_densities(cell);
+
         // log term of Equation 9
-        let logterm_positive = 1.0 /(2.0 * jp * self.membrane_surface_area) * self.current_i + ((1.0 / (2.0 * jp * self.membrane_surface_area) * self.current_i).powf(2.0) + 1.0 ).powf(0.5);
+        let logterm_positive = 1.0 /(2.0 * jp * self.membrane_surface_area) * cell.current + ((1.0 / (2.0 * jp * self.membrane_surface_area) * cell.current).powf(2.0) + 1.0 ).powf(0.5);
         // log term of Equation 10
-        let logterm_negative = 1.0 /(2.0 * jn * self.membrane_surface_area) * self.current_i + ((1.0 / (2.0 * jn * self.membrane_surface_area) * self.current_i).powf(2.0) + 1.0 ).powf(0.5);
+        let logterm_negative = 1.0 /(2.0 * jn * self.membrane_surface_area) * cell.current + ((1.0 / (2.0 * jn * self.membrane_surface_area) * cell.current).powf(2.0) + 1.0 ).powf(0.5);
 
-        // Positive overpotential of Equation 9
-        let positive_overpotential = ((2.0 * GAS_CONSTANT * TEMPERATURE) / FARADAY_CONSTANT) * logterm_positive.ln();
-        // Negative overpotential of Equation 10
-        let negative_overpotential = ((2.0 * GAS_CONSTANT * TEMPERATURE) / FARADAY_CONSTANT) * logterm_negative.ln();
+        let activation_voltage = (2.0 * GAS_CONSTANT * self.temperature) / FARADAY_CONSTANT;
 
-        let butler_volmer_overpotential = positive_overpotential - negative_overpotential;
+        let (positive_activation, negative_activation, positive_faradaic, negative_faradaic) = if self.double_layer {
+            // Overpotentials are held by the double layers, the faradaic current follows from inverting Equations 9 and 10
+            (cell.positive_overpotential, cell.negative_overpotential,
+             2.0 * jp * self.membrane_surface_area * (cell.positive_overpotential / activation_voltage).sinh(),
+             2.0 * jn * self.membrane_surface_area * (cell.negative_overpotential / activation_voltage).sinh())
+        } else {
+            // Positive overpotential of Equation 9 and negative overpotential of Equation 10
+            (activation_voltage * logterm_positive.ln(), activation_voltage * logterm_negative.ln(), cell.current, cell.current)
+        };
 
-        let nernst_overpotential = (GAS_CONSTANT * TEMPERATURE) / (Z_ELECTRON * FARADAY_CONSTANT) * ((self.anolyte_c2 * COPPER_UNITY) / (self.anolyte_c1 * self.catholyte_c1)).ln();
+        // Concentration overpotentials from the limiting currents of each electrode
+        let limiting_positive_c1 = limiting_current(mass_transfer_coefficient, self.membrane_surface_area, cell.anolyte_c1);
+        let limiting_positive_c2 = limiting_current(mass_transfer_coefficient, self.membrane_surface_area, cell.anolyte_c2);
+        let limiting_negative_c1 = limiting_current(mass_transfer_coefficient, self.membrane_surface_area, cell.catholyte_c1);
 
-        // Stack resistance overpotentials
-        let stack_overpotential = self.stack_resistance * self.current_i;
+        // Positive electrode, C1 depleted and C2 accumulated at the surface on charge and vice versa
+        let positive_concentration = (GAS_CONSTANT * self.temperature) / (Z_ELECTRON * FARADAY_CONSTANT)
+            * ((1.0 + current_fraction(positive_faradaic, limiting_positive_c2)) / (1.0 - current_fraction(positive_faradaic, limiting_positive_c1))).ln();
+        // Negative electrode, only C1 in solution as copper metal has unit activity
+        let negative_concentration = (GAS_CONSTANT * self.temperature) / (Z_ELECTRON * FARADAY_CONSTANT)
+            * (1.0 - current_fraction(negative_faradaic, limiting_negative_c1)).ln();
 
-        let voltage_offset:f32;
+        let nernst_overpotential = (GAS_CONSTANT * self.temperature) / (Z_ELECTRON * FARADAY_CONSTANT) * ((anolyte_a2 * COPPER_UNITY) / (anolyte_a1 * catholyte_a1)).ln();
 
-        if self.current_i > 0.0 {
-            voltage_offset = self.charge_offset;
+        let voltage_offset = if self.current_i > 0.0 {
+            self.charge_offset
         } else {
-            voltage_offset = self.discharge_offset;
+            self.discharge_offset
+        };
+
+        Polarisation {
+            open_circuit: self.standard_potential() + nernst_overpotential,
+            positive_activation,
+            negative_activation,
+            positive_concentration,
+            negative_concentration,
+            // Cell share of the stack resistance overpotential
+            ohmic: self.stack_resistance / self.cell_count * cell.factors.resistance * cell.current,
+            offset: voltage_offset,
+            positive_faradaic,
+            negative_faradaic,
         }
+    }
 
-        // System potenial
-        self.voltage = (butler_volmer_overpotential + nernst_overpotential + FORMAL_POTENTIAL + voltage_offset) * CELLS + stack_overpotential ;
+    fn activities(&self, cell: &Cell) -> (f32, f32, f32) {
+        // Free C1 and C2 in the anolyte and free C1 in the catholyte, total concentrations without speciation
+        match &self.speciation {
+            Some(speciation) => {
+                let (anolyte_a1, anolyte_a2) = speciation.free_concentrations(cell.anolyte_c1, cell.anolyte_c2);
+                let (catholyte_a1, _) = speciation.free_concentrations(cell.catholyte_c1, 0.0);
+
+                (anolyte_a1, anolyte_a2, catholyte_a1)
+            }
+            None => (cell.anolyte_c1, cell.anolyte_c2, cell.catholyte_c1),
+        }
+    }
+
+    fn standard_potential(&self) -> f32 {
+        // The formal potential already lumps complexation in, free ions need the true standard potential
+        match &self.speciation {
+            Some(speciation) => speciation.standard_potential,
+            None => FORMAL_POTENTIAL,
+        }
+    }
+
+    fn copper_coverage(&self, cell: &Cell) -> f32 {
+        // Fraction of the negative electrode covered by the deposit, thin deposits expose less copper
+        if self.copper_surface_effect {
+            (cell.copper_loading / COPPER_FULL_COVERAGE_LOADING).clamp(MINIMUM_COPPER_COVERAGE, 1.0)
+        } else {
+            1.0
+        }
+    }
+
+    fn copper_equivalent_concentration(&self) -> f32 {
+        // Plated copper expressed per catholyte volume, the basis of the C0c parameter
+        self.plated_copper() / (self.stack_volume() + self.catholyte_tank_volume)
+    }
+
+    fn plated_copper(&self) -> f32 {
+        // Copper on the negative electrodes of all cells (mol)
+        self.cells.iter().map(|cell| cell.copper_loading * self.membrane_surface_area * cell.count).sum()
+    }
+
+    fn electrolyte_moles(&self, concentration: impl Fn(&Cell) -> f32, tank_concentration: f32, tank_volume: f32) -> f32 {
+        // Species held in the half-cells of one side plus its tank (mol)
+        self.cells.iter().map(|cell| concentration(cell) * self.half_cell_volume * cell.count).sum::<f32>() + tank_concentration * tank_volume
+    }
+
+    fn cell_average(&self, value: impl Fn(&Cell) -> f32) -> f32 {
+        self.cells.iter().map(|cell| value(cell) * cell.count).sum::<f32>() / self.cell_count
+    }
+
+    fn stack_volume(&self) -> f32 {
+        // Electrolyte held in the half-cells on one side of the stack
+        self.half_cell_volume * self.cell_count
+    }
+
+    fn mass_transfer_coefficient(&self) -> f32 {
+        // Flow-through porous electrode correlation, km = a * v^0.4 with v the superficial velocity per cell
+        let velocity = self.flow_rate / self.cell_count / (self.electrode_width * self.electrode_thickness);
+
+        self.mass_transfer_factor * velocity.powf(0.4)
+    }
+
+    fn trial_voltage(&self, current: f32, interval: f32) -> f32 {
+        // Stack voltage at the end of the first step of the coming interval for a candidate current, leaves the model untouched
+        let mut trial = self.clone();
+        trial.split_interval(interval);
+        trial.current_i = current;
+        trial.time_step();
+
+        trial.voltage
+    }
+
+    fn constant_voltage_current(&self, voltage_setpoint: f32, interval: f32) -> f32 {
+        // Charge when the setpoint is above the open-circuit voltage, discharge otherwise
+        if self.trial_voltage(0.0, interval) < voltage_setpoint {
+            self.solve_current(1.0, |current| self.trial_voltage(current, interval) - voltage_setpoint)
+        } else {
+            self.solve_current(-1.0, |current| voltage_setpoint - self.trial_voltage(current, interval))
+        }
+    }
+
+    fn constant_power_current(&self, power: f32, interval: f32) -> f32 {
+        if power == 0.0 {
+            return 0.0;
+        }
+
+        self.solve_current(power.signum(), |current| (current * self.trial_voltage(current, interval)).abs() - power.abs())
+    }
+
+    fn solve_current(&self, direction: f32, residual: impl Fn(f32) -> f32) -> f32 {
+        // The residual is negative at zero current and grows with the current magnitude in the given
+        // direction. Expand the bracket until it changes sign, then bisect.
+        let mut low = 0.0;
+        let mut high = SOLVER_INITIAL_CURRENT;
+        let mut high_residual = residual(direction * high);
+
+        while high_residual < 0.0 && high < SOLVER_MAXIMUM_CURRENT {
+            let next_residual = residual(direction * high * 2.0);
+
+            // Past the peak, e.g. maximum power on discharge, the target cannot be reached
+            if next_residual < high_residual {
+                return direction * high;
+            }
+
+            low = high;
+            high *= 2.0;
+            high_residual = next_residual;
+        }
+
+        if high_residual < 0.0 {
+            return direction * high;
+        }
+
+        for _ in 0..SOLVER_ITERATIONS {
+            let middle = 0.5 * (low + high);
+
+            if residual(direction * middle) < 0.0 {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+
+        direction * 0.5 * (low + high)
+    }
+
+    fn thermal_mass(&self) -> f32 {
+        // Electrolyte on both sides provides the thermal mass
+        let electrolyte_volume = 2.0 * self.stack_volume() + self.anolyte_tank_volume + self.catholyte_tank_volume;
+
+        electrolyte_volume * ELECTROLYTE_DENSITY * ELECTROLYTE_HEAT_CAPACITY
     }
 
 }
 
-pub fn electrochem_model_sim(file_write: bool, individual: [f64; 12], real_current: Vec<f32>, real_voltage: Vec<f32>) -> f64 {
+impl OdeSystem for ElectrochemicalModel {
+    fn state(&self) -> Vec<f64> {
+        let mut state: Vec<f64> = Vec::with_capacity(self.cells.len() * CELL_STATES + 10);
+        for cell in &self.cells {
+            state.extend([cell.anolyte_c1, cell.anolyte_c2, cell.catholyte_c1, cell.copper_loading,
+                cell.positive_overpotential, cell.negative_overpotential].map(|value| value as f64));
+        }
+        state.extend([
+            self.anolyte_tank_c1, self.anolyte_tank_c2, self.catholyte_tank_c1,
+            self.anolyte_tank_volume, self.catholyte_tank_volume, self.temperature,
+            self.charge_passed, self.her_charge, self.oer_charge, self.shunt_charge,
+        ].map(|value| value as f64));
 
-    let mut electrochem_model = ElectrochemicalModel {
-        diffusion_number: individual[5] as f32, 
-        rate_constant_positive: individual[3] as f32,
-        rate_constant_negative: individual[4] as f32,
+        state
+    }
 
-        membrane_surface_area: 28.0 / 100.0 * 32.0 / 100.0,
-        membrane_thickness: 60e-6,
-        stack_resistance: individual[2] as f32,
-        time_step: 60.0,
+    fn set_state(&mut self, state: &[f64]) {
+        let (cell_states, stack_state) = state.split_at(self.cells.len() * CELL_STATES);
 
-        anolyte_c1: individual[0] as f32,
-        anolyte_c2: individual[8] as f32,
-        
-        catholyte_c1: individual[1] as f32,
-        catholyte_c0: individual[9] as f32,
+        for (cell, values) in self.cells.iter_mut().zip(cell_states.chunks(CELL_STATES)) {
+            cell.anolyte_c1 = values[ANOLYTE_C1] as f32;
+            cell.anolyte_c2 = values[ANOLYTE_C2] as f32;
+            cell.catholyte_c1 = values[CATHOLYTE_C1] as f32;
+            cell.copper_loading = values[COPPER_LOADING] as f32;
+            cell.positive_overpotential = values[POSITIVE_OVERPOTENTIAL] as f32;
+            cell.negative_overpotential = values[NEGATIVE_OVERPOTENTIAL] as f32;
+        }
 
-        current_i: 32.0,
+        let [anolyte_tank_c1, anolyte_tank_c2, catholyte_tank_c1, anolyte_tank_volume, catholyte_tank_volume,
+            temperature, charge_passed, her_charge, oer_charge, shunt_charge] = std::array::from_fn(|index| stack_state[index] as f32);
+        self.anolyte_tank_c1 = anolyte_tank_c1;
+        self.anolyte_tank_c2 = anolyte_tank_c2;
+        self.catholyte_tank_c1 = catholyte_tank_c1;
+        self.anolyte_tank_volume = anolyte_tank_volume;
+        self.catholyte_tank_volume = catholyte_tank_volume;
+        self.temperature = temperature;
+        self.charge_passed = charge_passed;
+        self.her_charge = her_charge;
+        self.oer_charge = oer_charge;
+        self.shunt_charge = shunt_charge;
 
-        voltage: 0.0,
-        cycle: 0.0,
+        // Stack resistance grows with cumulative throughput
+        if self.degradation {
+            self.stack_resistance = self.initial_stack_resistance + self.resistance_growth * self.charge_passed / 3600.0;
+        }
+    }
 
-        charge_offset: individual[6] as f32,
-        discharge_offset: individual[7] as f32
-    };
+    fn derivative(&self) -> Vec<f64> {
+        let mass_transfer_coefficient = self.mass_transfer_coefficient();
+        let polarisations: Vec<Polarisation> = self.cells.iter()
+            .map(|cell| self.polarisation(cell, mass_transfer_coefficient))
+            .collect();
 
-    // Arrays to capture data for plotting
-    let mut voltage_data = Vec::new();
+        let mut rates = StateRates::new(self.cells.len());
 
-    let mut catholyte_c1_data = Vec::new();
-    let mut catholyte_c0_data = Vec::new();
+        self.reaction_rates(&polarisations, &mut rates);
+        self.degradation_rates(&mut rates);
+        self.diffusion_rates(&mut rates);
+        self.water_transfer_rates(&mut rates);
+        self.flow_rates(&mut rates);
+        self.thermal_rates(&polarisations, &mut rates);
 
-    let mut anolyte_c1_data = Vec::new();
-    let mut anolyte_c2_data = Vec::new();
+        rates.to_vec()
+    }
+}
 
-    let mut time_data = Vec::new();
+fn electrode_impedance(charge_transfer: f32, mass_transfer: f32, capacitance: f32, warburg_shape: Complex, angular_frequency: f64) -> Complex {
+    // Randles circuit, charge transfer in series with bounded diffusion, in parallel with the double layer
+    let faradaic = Complex::real(charge_transfer as f64) + warburg_shape * mass_transfer as f64;
+    let capacitive_admittance = Complex::new(0.0, angular_frequency * capacitance as f64);
 
-    // Tracking simulation time
-    let mut time_counter:f32 = 0.0;
+    (faradaic.inverse() + capacitive_admittance).inverse()
+}
 
-    for current in real_current {
-        electrochem_model.current_i = current;
-        electrochem_model.time_step();
+fn limiting_current(mass_transfer_coefficient: f32, area: f32, concentration: f32) -> f32 {
+    Z_ELECTRON * FARADAY_CONSTANT * mass_transfer_coefficient * area * concentration
+}
 
-        voltage_data.push(electrochem_model.voltage);
-        catholyte_c1_data.push(electrochem_model.catholyte_c1);
-        catholyte_c0_data.push(electrochem_model.catholyte_c0);
+fn current_fraction(current: f32, limiting_current: f32) -> f32 {
+    // Signed fraction of the limiting current, kept just short of the limit so the logarithm stays finite
+    if current == 0.0 {
+        return 0.0;
+    }
 
-        anolyte_c1_data.push(electrochem_model.anolyte_c1);
-        anolyte_c2_data.push(electrochem_model.anolyte_c2);
+    // No transport to the electrode, e.g. no reactant left, any current runs straight into the limit
+    if limiting_current <= 0.0 || !limiting_current.is_finite() {
+        return LIMITING_CURRENT_FRACTION.copysign(current);
+    }
 
-        time_data.push(time_counter);
+    (current / limiting_current).clamp(-LIMITING_CURRENT_FRACTION, LIMITING_CURRENT_FRACTION)
+}
 
-        time_counter += 60.0; 
+/// Operating points set_operating_point can hold, fully charged or discharged electrolyte leaves the Nernst term without one oxidation state
+pub fn check_operating_point(state_of_charge: f32, temperature: f32) -> Result<(), String> {
+    if state_of_charge <= 0.0 || state_of_charge >= 1.0 {
+        return Err(format!("state of charge {} must lie between 0 and 1, exclusive", state_of_charge));
+    }
+    if temperature <= 0.0 {
+        return Err(format!("temperature {} K must be positive", temperature));
     }
 
-    let fitness = fitness_function(time_data.clone(), real_voltage.clone(), voltage_data.clone());
+    Ok(())
+}
+
+pub fn electrochem_model_sim(output_directory: Option<&Path>, parameters: &ModelParameters, options: &ModelOptions, stack: &StackConfig, real_time: Vec<f32>, real_current: Vec<f32>, real_voltage: Vec<f32>) -> f64 {
+    let schedule = real_current.into_iter().map(OperatingMode::ConstantCurrent).collect();
+
+    electrochem_model_sim_controlled(output_directory, parameters, options, stack, real_time, schedule, real_voltage)
+}
+
+/// Simulate the schedule at the sample times (s), each sample's mode is held from its timestamp to the next one.
+/// With an output directory the time series, capacities and cycle summary are written into it.
+pub fn electrochem_model_sim_controlled(output_directory: Option<&Path>, parameters: &ModelParameters, options: &ModelOptions, stack: &StackConfig, real_time: Vec<f32>, schedule: Vec<OperatingMode>, real_voltage: Vec<f32>) -> f64 {
+
+    let mut electrochem_model = ElectrochemicalModel::new(parameters, options, stack);
+    let theoretical_capacity = electrochem_model.theoretical_capacity();
+
+    // The logged current is only known when the data drives the model
+    let measured_current: Option<Vec<f32>> = schedule.iter()
+        .map(|mode| match *mode {
+            OperatingMode::ConstantCurrent(current) => Some(current),
+            _ => None,
+        })
+        .collect();
 
-    if file_write {
-        write_output(time_data, real_voltage,voltage_data, catholyte_c1_data, catholyte_c0_data, anolyte_c1_data, anolyte_c2_data);
+    // Only the voltage enters the fitness, the full time series is kept when it is written out
+    let mut simulated_voltage = Vec::with_capacity(real_time.len());
+    let mut simulation_data = output_directory.map(|_| SimulationData::default());
+
+    let mut controller = ModeController::default();
+
+    for (index, (&time, mode)) in real_time.iter().zip(schedule).enumerate() {
+        // Zero-order hold up to the next timestamp, so a rest logged before a dropout rests through the gap.
+        // Samples stamped at the start of their averaging bin cover that bin, the last sample covers one time step
+        let interval = real_time.get(index + 1).map_or(stack.time_step, |next| (next - time).max(0.0));
+
+        let current = controller.current(&electrochem_model, mode, interval);
+        electrochem_model.advance_interval(current, interval);
+
+        simulated_voltage.push(electrochem_model.voltage);
+        if let Some(simulation_data) = &mut simulation_data {
+            simulation_data.record(time, &electrochem_model);
+        }
+    }
+
+    let fitness = fitness_function(real_time, real_voltage.clone(), simulated_voltage);
+
+    if let (Some(directory), Some(simulation_data)) = (output_directory, simulation_data) {
+        write_output(&directory.join("output.csv"), &simulation_data, real_voltage.clone());
+
+        // Split of the charge passed per cell into useful and parasitic charge
+        let charge_passed = electrochem_model.charge_passed / 3600.0;
+        let her_charge = electrochem_model.her_charge / 3600.0;
+        let oer_charge = electrochem_model.oer_charge / 3600.0;
+
+        println!("Charge passed per cell: {:.3} Ah", charge_passed);
+        println!("HER charge: {:.3} Ah ({:.2} %)", her_charge, her_charge / charge_passed * 100.0);
+        println!("Cl2/O2 charge: {:.3} Ah ({:.2} %)", oer_charge, oer_charge / charge_passed * 100.0);
+
+        if electrochem_model.shunt.is_some() {
+            let shunt_charge = electrochem_model.shunt_charge / 3600.0;
+            println!("Shunt charge: {:.3} Ah ({:.2} %)", shunt_charge, shunt_charge / charge_passed * 100.0);
+        }
+
+        let capacities = capacity_per_cycle(&simulation_data.time, &simulation_data.current, &simulation_data.cycle);
+        if let Err(e) = write_capacity_report(&directory.join("capacity.csv").to_string_lossy(), &capacities) {
+            eprintln!("Error writing capacity report: {}", e);
+        }
+
+        // Cycles follow the simulated counter for both, the measured samples share its timestamps
+        let simulated = cycle_metrics(&simulation_data.time, &simulation_data.current, &simulation_data.voltage, &simulation_data.cycle, theoretical_capacity);
+        let measured = measured_current.map(|current| cycle_metrics(&simulation_data.time, &current, &real_voltage, &simulation_data.cycle, theoretical_capacity));
+
+        if let Err(e) = write_cycle_summary(&directory.join("cycle_summary.csv").to_string_lossy(), &simulated, measured.as_deref()) {
+            eprintln!("Error writing cycle summary: {}", e);
+        }
     }
 
     fitness
     
 }
 
+/// Resolves the operating mode into a current each step, keeping track of the CC-CV phase
+#[derive(Default)]
+pub struct ModeController {
+    constant_voltage_phase: bool,
+    taper_finished: bool,
+    previous_mode: Option<OperatingMode>,
+}
+
+impl ModeController {
+    /// Current to hold over the coming interval (s)
+    pub fn current(&mut self, model: &ElectrochemicalModel, mode: OperatingMode, interval: f32) -> f32 {
+        // CC-CV phase is reset whenever the schedule moves to a different mode
+        if !same_cccv_step(self.previous_mode, mode) {
+            self.constant_voltage_phase = false;
+            self.taper_finished = false;
+        }
+        self.previous_mode = Some(mode);
+
+        match mode {
+            OperatingMode::ConstantCurrent(current) => current,
+            OperatingMode::ConstantVoltage(voltage) => model.constant_voltage_current(voltage, interval),
+            OperatingMode::ConstantPower(power) => model.constant_power_current(power, interval),
+            OperatingMode::ConstantCurrentConstantVoltage { current, voltage_limit, current_cutoff } => {
+                // Switch to the voltage hold once the constant current would cross the limit
+                if !self.constant_voltage_phase {
+                    let voltage = model.trial_voltage(current, interval);
+                    self.constant_voltage_phase = (current > 0.0 && voltage >= voltage_limit) || (current < 0.0 && voltage <= voltage_limit);
+                }
+
+                if self.taper_finished {
+                    0.0
+                } else if self.constant_voltage_phase {
+                    let taper_current = model.constant_voltage_current(voltage_limit, interval);
+
+                    // Hold finished, rest until the schedule moves on
+                    if taper_current.abs() < current_cutoff {
+                        self.taper_finished = true;
+                        0.0
+                    } else {
+                        taper_current
+                    }
+                } else {
+                    current
+                }
+            }
+        }
+    }
+
+    /// Whether the current CC-CV step has tapered down to its cut-off
+    pub fn taper_finished(&self) -> bool {
+        self.taper_finished
+    }
+}
+
+fn same_cccv_step(previous: Option<OperatingMode>, current: OperatingMode) -> bool {
+    // Consecutive steps of the same CC-CV setpoints belong to one charge or discharge
+    match (previous, current) {
+        (Some(OperatingMode::ConstantCurrentConstantVoltage { current: a, voltage_limit: b, current_cutoff: c }),
+         OperatingMode::ConstantCurrentConstantVoltage { current: d, voltage_limit: e, current_cutoff: f }) => a == d && b == e && c == f,
+        _ => false,
+    }
+}
+
+#[derive(Default)]
+struct SimulationData {
+    time: Vec<f32>,
+    voltage: Vec<f32>,
+    current: Vec<f32>,
+    temperature: Vec<f32>,
+    cycle: Vec<u32>,
+    stack_resistance: Vec<f32>,
+    anolyte_volume: Vec<f32>,
+    catholyte_volume: Vec<f32>,
+
+    her_current: Vec<f32>,
+    oer_current: Vec<f32>,
+    charge_passed: Vec<f32>,
+    her_charge: Vec<f32>,
+    oer_charge: Vec<f32>,
+
+    shunt_current: Vec<f32>,
+    shunt_loss: Vec<f32>,
+
+    positive_capacitive_current: Vec<f32>,
+    negative_capacitive_current: Vec<f32>,
+
+    catholyte_c1: Vec<f32>,
+    catholyte_c0: Vec<f32>,
+    copper_loading: Vec<f32>,
+
+    anolyte_c1: Vec<f32>,
+    anolyte_c2: Vec<f32>,
+
+    catholyte_tank_c1: Vec<f32>,
+
+    anolyte_tank_c1: Vec<f32>,
+    anolyte_tank_c2: Vec<f32>,
+
+    positive_state_of_charge: Vec<f32>,
+    negative_state_of_charge: Vec<f32>,
+
+    voltage_breakdown: Vec<VoltageBreakdown>,
+
+    cell_voltages: Vec<Vec<f32>>
+}
+
+impl SimulationData {
+    fn record(&mut self, time: f32, model: &ElectrochemicalModel) {
+        self.time.push(time);
+        self.voltage.push(model.voltage);
+        self.current.push(model.current_i);
+        self.cycle.push(model.cycle as u32);
+        self.stack_resistance.push(model.stack_resistance);
+        self.anolyte_volume.push(model.stack_volume() + model.anolyte_tank_volume);
+        self.catholyte_volume.push(model.stack_volume() + model.catholyte_tank_volume);
+        self.temperature.push(model.temperature);
+
+        // Averages over the cells of the stack
+        self.her_current.push(model.cell_average(|cell| cell.her_current));
+        self.oer_current.push(model.cell_average(|cell| cell.oer_current));
+        self.charge_passed.push(model.charge_passed / 3600.0);
+        self.her_charge.push(model.her_charge / 3600.0);
+        self.oer_charge.push(model.oer_charge / 3600.0);
+
+        self.shunt_current.push(model.cell_average(|cell| model.current_i - cell.current));
+        self.shunt_loss.push(model.shunt_loss);
+
+        self.positive_capacitive_current.push(model.cell_average(|cell| cell.current - cell.positive_faradaic));
+        self.negative_capacitive_current.push(model.cell_average(|cell| cell.current - cell.negative_faradaic));
+
+        self.catholyte_c1.push(model.cell_average(|cell| cell.catholyte_c1));
+        self.catholyte_c0.push(model.copper_equivalent_concentration());
+        self.copper_loading.push(model.cell_average(|cell| cell.copper_loading));
+
+        self.anolyte_c1.push(model.cell_average(|cell| cell.anolyte_c1));
+        self.anolyte_c2.push(model.cell_average(|cell| cell.anolyte_c2));
+
+        self.catholyte_tank_c1.push(model.catholyte_tank_c1);
+
+        self.anolyte_tank_c1.push(model.anolyte_tank_c1);
+        self.anolyte_tank_c2.push(model.anolyte_tank_c2);
+
+        self.positive_state_of_charge.push(model.positive_state_of_charge());
+        self.negative_state_of_charge.push(model.negative_state_of_charge());
+
+        self.voltage_breakdown.push(model.voltage_breakdown());
+
+        self.cell_voltages.push(model.cell_voltages());
+    }
+}
+
 fn fitness_function(time: Vec<f32>, real_voltage: Vec<f32>, simulated_voltage: Vec<f32>) -> f64 {
 
     let mut fitness: f64 = 0.0;
@@ -232,23 +1436,123 @@ fn fitness_function(time: Vec<f32>, real_voltage: Vec<f32>, simulated_voltage: V
     fitness
 }
 
-fn write_output(time_data: Vec<f32>, real_voltage: Vec<f32>, voltage_data: Vec<f32>, catholyte_c1_data: Vec<f32>, catholyte_c0_data: Vec<f32>, anolyte_c1_data: Vec<f32>, anolyte_c2_data: Vec<f32>) {
-    let file = File::create("output.csv").expect("Unable to create file");
+fn write_output(path: &Path, data: &SimulationData, real_voltage: Vec<f32>) {
+    let file = File::create(path).expect("Unable to create file");
     let mut writer = BufWriter::new(&file);
 
     let mut counter = 0;
 
-    while counter < voltage_data.len() {
+    while counter < data.voltage.len() {
         if counter == 0 {
-            writeln!(writer, "Time, Real Voltage, Simulated Voltage, c1c, c0c, c1a, c2a").expect("Failed to write data");
+            write!(writer, "Time, Real Voltage, Simulated Voltage, c1c, c0c, c1a, c2a, c1c tank, c1a tank, c2a tank, Temperature, Cu loading, Simulated Current, HER current, Cl2/O2 current, Charge passed (Ah), HER charge (Ah), Cl2/O2 charge (Ah), Cycle, Stack resistance, Anolyte volume, Catholyte volume, Shunt current, Shunt loss, Positive capacitive current, Negative capacitive current, Open circuit voltage, Activation overpotential, Concentration overpotential, Ohmic overpotential, Voltage offset, Positive SOC, Negative SOC").expect("Failed to write data");
+            for cell in 0..data.cell_voltages[counter].len() {
+                write!(writer, ", Cell {} voltage", cell + 1).expect("Failed to write data");
+            }
+            writeln!(writer).expect("Failed to write data");
         }
 
-        writeln!(writer, "{}, {}, {}, {}, {}, {}, {}", 
-        time_data[counter], real_voltage[counter], voltage_data[counter], 
-        catholyte_c1_data[counter], catholyte_c0_data[counter], 
-        anolyte_c1_data[counter], anolyte_c2_data[counter])
+        let breakdown = &data.voltage_breakdown[counter];
+
+        write!(writer, "{}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}", 
+        data.time[counter], real_voltage[counter], data.voltage[counter], 
+        data.catholyte_c1[counter], data.catholyte_c0[counter], 
+        data.anolyte_c1[counter], data.anolyte_c2[counter],
+        data.catholyte_tank_c1[counter],
+        data.anolyte_tank_c1[counter], data.anolyte_tank_c2[counter],
+        data.temperature[counter], data.copper_loading[counter], data.current[counter],
+        data.her_current[counter], data.oer_current[counter],
+        data.charge_passed[counter], data.her_charge[counter], data.oer_charge[counter],
+        data.cycle[counter], data.stack_resistance[counter],
+        data.anolyte_volume[counter], data.catholyte_volume[counter],
+        data.shunt_current[counter], data.shunt_loss[counter],
+        data.positive_capacitive_current[counter], data.negative_capacitive_current[counter],
+        breakdown.open_circuit, breakdown.activation, breakdown.concentration, breakdown.ohmic, breakdown.offset,
+        data.positive_state_of_charge[counter], data.negative_state_of_charge[counter])
         .expect("Failed to write data");
+
+        for cell_voltage in &data.cell_voltages[counter] {
+            write!(writer, ", {}", cell_voltage).expect("Failed to write data");
+        }
+        writeln!(writer).expect("Failed to write data");
         
         counter += 1;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model_parameters::GENE_COUNT;
+
+    fn test_parameters() -> ModelParameters {
+        // Mid-range values of the default fit bounds, in gene order
+        let genes: [f64; GENE_COUNT] = [
+            2000.0, 2000.0, 0.1, 1.0e-5, 1.0e-5, 1.0e-12, 0.0, 0.0, 100.0, 100.0, 1.0e-2,
+            1.0e-6, 0.12, 1.0e-6, 0.12, 1.0e-8, 1.0e-6, 1.0e-5, 1.0, 1.0e-12, 100.0, 100.0,
+        ];
+        ModelParameters::from_genes(&genes)
+    }
+
+    fn assert_finite(model: &mut ElectrochemicalModel) {
+        assert!(model.voltage().is_finite(), "stack voltage {}", model.voltage());
+        assert!(model.state().iter().all(|value| value.is_finite()), "state {:?}", model.state());
+    }
+
+    // Uneven sample spacing, none longer than the configured step so every interval is a single step
+    const INTERVALS: [f32; 6] = [30.0, 60.0, 10.0, 45.0, 5.0, 60.0];
+
+    #[test]
+    fn constant_voltage_holds_the_setpoint() {
+        let mut model = ElectrochemicalModel::new(&test_parameters(), &ModelOptions::default(), &StackConfig::default());
+        let mut controller = ModeController::default();
+        let setpoint = model.trial_voltage(0.0, 60.0) + 1.0;
+
+        for interval in INTERVALS {
+            let current = controller.current(&model, OperatingMode::ConstantVoltage(setpoint), interval);
+            model.advance_interval(current, interval);
+
+            assert!(model.current() > 0.0);
+            assert!((model.voltage() - setpoint).abs() < 1.0e-3, "{} V against {} V", model.voltage(), setpoint);
+        }
+    }
+
+    #[test]
+    fn constant_power_holds_the_target() {
+        let mut model = ElectrochemicalModel::new(&test_parameters(), &ModelOptions::default(), &StackConfig::default());
+        let mut controller = ModeController::default();
+
+        for power in [600.0, -150.0] {
+            for interval in INTERVALS {
+                let current = controller.current(&model, OperatingMode::ConstantPower(power), interval);
+                model.advance_interval(current, interval);
+
+                let delivered = model.current() * model.voltage();
+                assert!((delivered - power).abs() < 1.0e-3 * power.abs(), "{} W against {} W", delivered, power);
+            }
+        }
+    }
+
+    #[test]
+    fn shunted_stack_stays_finite_through_a_cycle() {
+        // README example network on the default stack, charged then discharged past the stored charge
+        let stack = StackConfig {
+            shunt: Some(ShuntConfig {
+                anolyte_channel_resistance: 100.0,
+                anolyte_manifold_resistance: 1.0,
+                catholyte_channel_resistance: 100.0,
+                catholyte_manifold_resistance: 1.0,
+            }),
+            ..StackConfig::default()
+        };
+        let mut model = ElectrochemicalModel::new(&test_parameters(), &ModelOptions::default(), &stack);
+
+        for _ in 0..300 {
+            model.advance(30.0);
+            assert_finite(&mut model);
+        }
+        for _ in 0..600 {
+            model.advance(-30.0);
+            assert_finite(&mut model);
+        }
+    }
+}
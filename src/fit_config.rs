@@ -0,0 +1,437 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::electrochem_model::ModelOptions;
+use crate::genetic_algorithm::{ParameterScale, Population};
+use crate::model_parameters::{GENE_COUNT, PARAMETER_KEYS};
+
+/// Everything a fit depends on: data and stack files, genetic algorithm settings and the parameter search space.
+/// A config file only needs the values that differ from the defaults, the resolved config is saved with the results.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FitConfig {
+    /// Measured time, voltage and current
+    pub data: String,
+    pub stack: String,
+    /// Measured impedance spectrum, added to the fitness when the file exists
+    pub eis: String,
+    pub output_dir: String,
+    pub genetic_algorithm: GeneticAlgorithmSettings,
+    /// Search space of every model parameter, by name
+    pub parameters: BTreeMap<String, ParameterSetting>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GeneticAlgorithmSettings {
+    pub population: u64,
+    pub generations: u64,
+    pub mutation_intensity: f64,
+    pub crossover_rate: f64,
+    pub mutation_rate: f64,
+    /// Fraction of the population kept as elite
+    pub elite_size: f64,
+    /// Drawn at random and recorded in the saved config when absent
+    pub seed: Option<u64>,
+}
+
+/// Bounds and starting point of one parameter, in the parameter's own units
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ParameterSetting {
+    pub lower: f64,
+    pub upper: f64,
+    /// Seeded into the initial population, the value used when fixed
+    pub initial: Option<f64>,
+    /// Held at the initial value instead of being fitted
+    #[serde(default)]
+    pub fixed: bool,
+    /// Space the parameter is sampled and mutated in, log for parameters spanning decades
+    #[serde(default)]
+    pub scale: ParameterScale,
+}
+
+impl ParameterSetting {
+    fn linear(lower: f64, upper: f64) -> Self {
+        ParameterSetting { lower, upper, initial: None, fixed: false, scale: ParameterScale::Linear }
+    }
+
+    fn log(lower: f64, upper: f64) -> Self {
+        ParameterSetting { scale: ParameterScale::Log, ..ParameterSetting::linear(lower, upper) }
+    }
+}
+
+impl Default for GeneticAlgorithmSettings {
+    fn default() -> Self {
+        GeneticAlgorithmSettings {
+            population: 500000,
+            generations: 150,
+            mutation_intensity: 0.3,
+            crossover_rate: 0.7,
+            mutation_rate: 0.3,
+            elite_size: 0.1,
+            seed: None,
+        }
+    }
+}
+
+impl Default for FitConfig {
+    fn default() -> Self {
+        let parameters: BTreeMap<String, ParameterSetting> = [
+            ("anolyte_c1", ParameterSetting::linear(1000.0, 3000.0)),
+            ("catholyte_c1", ParameterSetting::linear(1000.0, 3000.0)),
+            ("stack_resistance", ParameterSetting::linear(0.0, 0.5)),
+            ("rate_constant_positive", ParameterSetting::log(1.0e-8, 1.0e0)),
+            ("rate_constant_negative", ParameterSetting::log(1.0e-8, 1.0e0)),
+            ("diffusion_coefficient", ParameterSetting::log(1.0e-14, 1.0e-10)),
+            ("charge_offset", ParameterSetting::linear(-0.5, 0.5)),
+            ("discharge_offset", ParameterSetting::linear(-0.5, 0.5)),
+            ("anolyte_c2", ParameterSetting::linear(0.0, 500.0)),
+            ("catholyte_c0", ParameterSetting::linear(0.0, 500.0)),
+            ("mass_transfer_factor", ParameterSetting::log(1.0e-4, 1.0e-1)),
+            ("her_exchange_current", ParameterSetting::log(1.0e-8, 1.0e-1)),
+            ("her_tafel_slope", ParameterSetting::linear(0.03, 0.3)),
+            ("oer_exchange_current", ParameterSetting::log(1.0e-8, 1.0e-1)),
+            ("oer_tafel_slope", ParameterSetting::linear(0.03, 0.3)),
+            ("copper_loss_rate", ParameterSetting::linear(0.0, 1.0e-6)),
+            ("electrolyte_crossover", ParameterSetting::linear(-1.0e-5, 1.0e-5)),
+            ("resistance_growth", ParameterSetting::linear(0.0, 1.0e-4)),
+            ("electro_osmotic_drag", ParameterSetting::linear(0.0, 3.0)),
+            ("osmotic_permeability", ParameterSetting::linear(0.0, 1.0e-11)),
+            ("positive_capacitance", ParameterSetting::log(1.0, 1.0e4)),
+            ("negative_capacitance", ParameterSetting::log(1.0, 1.0e4)),
+        ]
+        .into_iter()
+        .map(|(key, setting)| (key.to_string(), setting))
+        .collect();
+        debug_assert!(parameters.len() == GENE_COUNT && PARAMETER_KEYS.iter().all(|key| parameters.contains_key(*key)));
+
+        FitConfig {
+            data: "data.csv".to_string(),
+            stack: "stack.json".to_string(),
+            eis: "eis.json".to_string(),
+            output_dir: ".".to_string(),
+            genetic_algorithm: GeneticAlgorithmSettings::default(),
+            parameters,
+        }
+    }
+}
+
+impl FitConfig {
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        // Values in the file are laid over the defaults, so a parameter can be given by its fixed flag alone
+        let mut config = serde_json::to_value(FitConfig::default())?;
+        let file: Value = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        merge(&mut config, file);
+
+        if let Some(name) = config["parameters"].as_object().and_then(|parameters| parameters.keys().find(|name| !PARAMETER_KEYS.contains(&name.as_str()))) {
+            return Err(format!("unknown parameter {}", name).into());
+        }
+
+        let config: FitConfig = serde_json::from_value(config)?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        serde_json::to_writer_pretty(File::create(path)?, self)?;
+        Ok(())
+    }
+
+    /// Hold a parameter at a value instead of fitting it
+    pub fn fix(&mut self, name: &str, value: f64) -> Result<(), Box<dyn Error>> {
+        let setting = self.parameters.get_mut(name).ok_or_else(|| format!("unknown parameter {}", name))?;
+        setting.fixed = true;
+        setting.initial = Some(value);
+
+        Ok(())
+    }
+
+    /// Fit a parameter the config holds fixed, its value is kept as the initial guess
+    pub fn free(&mut self, name: &str) -> Result<(), Box<dyn Error>> {
+        let setting = self.parameters.get_mut(name).ok_or_else(|| format!("unknown parameter {}", name))?;
+        setting.fixed = false;
+
+        Ok(())
+    }
+
+    /// Hold the parameters the model options leave without effect at their initial value, or the bound nearest zero.
+    /// The double-layer capacitances still shape a fitted impedance spectrum. Returns the parameters that were free before.
+    pub fn fix_unused(&mut self, options: &ModelOptions, fit_impedance: bool) -> Vec<&'static str> {
+        let mut unused = Vec::new();
+        if !options.side_reactions {
+            unused.extend(["her_exchange_current", "her_tafel_slope", "oer_exchange_current", "oer_tafel_slope"]);
+        }
+        if !options.degradation {
+            unused.extend(["copper_loss_rate", "electrolyte_crossover", "resistance_growth"]);
+        }
+        if !options.double_layer && !fit_impedance {
+            unused.extend(["positive_capacitance", "negative_capacitance"]);
+        }
+
+        unused.retain(|name| {
+            let setting = self.parameters.get_mut(*name).unwrap();
+            if setting.fixed {
+                return false;
+            }
+
+            setting.fixed = true;
+            setting.initial = Some(setting.initial.unwrap_or(0.0).clamp(setting.lower, setting.upper));
+
+            true
+        });
+
+        unused
+    }
+
+    /// Names of the fixed parameters, in gene order
+    pub fn fixed_parameters(&self) -> Vec<String> {
+        PARAMETER_KEYS.iter().filter(|key| self.parameters[**key].fixed).map(|key| key.to_string()).collect()
+    }
+
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        for (name, setting) in &self.parameters {
+            if setting.lower > setting.upper {
+                return Err(format!("{} has a lower bound above its upper bound", name).into());
+            }
+            if setting.scale == ParameterScale::Log && setting.lower <= 0.0 {
+                return Err(format!("{} is sampled on a log scale but its lower bound is not positive", name).into());
+            }
+            if setting.fixed && setting.initial.is_none() {
+                return Err(format!("{} is fixed without an initial value", name).into());
+            }
+            if let Some(initial) = setting.initial {
+                if !setting.fixed && (initial < setting.lower || initial > setting.upper) {
+                    return Err(format!("{} has its initial value outside the bounds", name).into());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn setting(&self, index: usize) -> &ParameterSetting {
+        &self.parameters[PARAMETER_KEYS[index]]
+    }
+
+    /// Population laid out by the config, the search runs over the parameters that are not fixed
+    pub fn population(&self) -> Population {
+        let settings = &self.genetic_algorithm;
+
+        let mut parameter_bounds_lower = [0.0; GENE_COUNT];
+        let mut parameter_bounds_upper = [0.0; GENE_COUNT];
+        let mut parameter_scales = [ParameterScale::Linear; GENE_COUNT];
+        let mut fixed_genes = [None; GENE_COUNT];
+
+        for index in 0..GENE_COUNT {
+            let setting = self.setting(index);
+
+            parameter_bounds_lower[index] = setting.lower;
+            parameter_bounds_upper[index] = setting.upper;
+            parameter_scales[index] = setting.scale;
+            fixed_genes[index] = setting.initial.filter(|_| setting.fixed);
+        }
+
+        Population {
+            best_fitness: 0.0,
+            worst_fitness: 0.0,
+            average_fitness: 0.0,
+
+            mutation_intensity: settings.mutation_intensity,
+            crossover_rate: settings.crossover_rate,
+            mutation_rate: settings.mutation_rate,
+            elite_size: settings.elite_size,
+
+            individual_list: Vec::new(),
+
+            parameter_bounds_upper,
+            parameter_bounds_lower,
+            parameter_scales,
+            fixed_genes,
+
+            maximum_generation: settings.generations,
+            current_generation: 0,
+
+            rng: settings.seed.map_or_else(StdRng::from_entropy, StdRng::seed_from_u64),
+        }
+    }
+
+    /// Initial guesses in gene order, None where the parameter has no guess
+    pub fn initial_guess(&self) -> [Option<f64>; GENE_COUNT] {
+        std::array::from_fn(|index| self.setting(index).initial)
+    }
+}
+
+fn merge(base: &mut Value, overlay: Value) {
+    // Objects are merged key by key, anything else replaces the default
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn load_str(name: &str, contents: &str) -> Result<FitConfig, Box<dyn Error>> {
+        let path = std::env::temp_dir().join(format!("fit_config_{}_{}.json", name, std::process::id()));
+        std::fs::write(&path, contents)?;
+        let config = FitConfig::load(&path.to_string_lossy());
+        std::fs::remove_file(&path)?;
+
+        config
+    }
+
+    #[test]
+    fn merge_overlays_objects_key_by_key() {
+        let mut base = json!({ "a": 1, "nested": { "b": 2, "c": 3 }, "list": [1, 2] });
+        merge(&mut base, json!({ "nested": { "c": 4 }, "list": [5], "d": 6 }));
+
+        assert_eq!(base, json!({ "a": 1, "nested": { "b": 2, "c": 4 }, "list": [5], "d": 6 }));
+    }
+
+    #[test]
+    fn partial_config_keeps_the_defaults() {
+        let config = load_str("partial", r#"{
+            "genetic_algorithm": { "population": 100 },
+            "parameters": { "stack_resistance": { "fixed": true, "initial": 0.05 } }
+        }"#).unwrap();
+        let defaults = FitConfig::default();
+
+        assert_eq!(config.genetic_algorithm.population, 100);
+        assert_eq!(config.genetic_algorithm.generations, defaults.genetic_algorithm.generations);
+        assert_eq!(config.data, defaults.data);
+
+        // The flag and guess are laid over the default bounds and scale
+        let resistance = &config.parameters["stack_resistance"];
+        assert!(resistance.fixed);
+        assert_eq!(resistance.initial, Some(0.05));
+        assert_eq!((resistance.lower, resistance.upper), (0.0, 0.5));
+        assert_eq!(config.parameters.len(), GENE_COUNT);
+        assert!(config.parameters["rate_constant_positive"].scale == ParameterScale::Log);
+        assert_eq!(config.fixed_parameters(), vec!["stack_resistance".to_string()]);
+    }
+
+    #[test]
+    fn unknown_parameters_are_rejected() {
+        let Err(error) = load_str("unknown", r#"{ "parameters": { "stack_resistence": { "fixed": true, "initial": 0.05 } } }"#) else {
+            panic!("misspelt parameter accepted");
+        };
+        assert_eq!(error.to_string(), "unknown parameter stack_resistence");
+    }
+
+    #[test]
+    fn validate_rejects_inconsistent_settings() {
+        let invalid = [
+            ("stack_resistance", ParameterSetting::linear(0.5, 0.1), "lower bound above its upper bound"),
+            ("rate_constant_positive", ParameterSetting::log(0.0, 1.0), "log scale"),
+            ("anolyte_c1", ParameterSetting { fixed: true, ..ParameterSetting::linear(1000.0, 3000.0) }, "fixed without an initial value"),
+            ("anolyte_c1", ParameterSetting { initial: Some(500.0), ..ParameterSetting::linear(1000.0, 3000.0) }, "initial value outside the bounds"),
+        ];
+
+        assert!(FitConfig::default().validate().is_ok());
+        for (name, setting, message) in invalid {
+            let mut config = FitConfig::default();
+            config.parameters.insert(name.to_string(), setting);
+
+            let error = config.validate().unwrap_err().to_string();
+            assert!(error.starts_with(name) && error.contains(message), "{}", error);
+        }
+
+        // A fixed value outside the fit bounds is allowed
+        let mut config = FitConfig::default();
+        config.fix("anolyte_c1", 500.0).unwrap();
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn fix_and_free_toggle_the_search() {
+        let mut config = FitConfig::default();
+        config.fix("stack_resistance", 0.05).unwrap();
+        assert!(config.fix("stack_resistence", 0.05).is_err());
+        assert_eq!(config.population().fixed_genes[2], Some(0.05));
+
+        // Freed parameters keep the fixed value as their initial guess
+        config.free("stack_resistance").unwrap();
+        assert_eq!(config.population().fixed_genes[2], None);
+        assert_eq!(config.initial_guess()[2], Some(0.05));
+    }
+
+    #[test]
+    fn fix_unused_leaves_user_fixed_parameters_alone() {
+        let mut config = FitConfig::default();
+        config.fix("her_tafel_slope", 0.1).unwrap();
+
+        let fixed = config.fix_unused(&ModelOptions::default(), false);
+        assert_eq!(fixed, ["her_exchange_current", "oer_exchange_current", "oer_tafel_slope", "copper_loss_rate",
+            "electrolyte_crossover", "resistance_growth", "positive_capacitance", "negative_capacitance"]);
+        assert_eq!(config.parameters["her_tafel_slope"].initial, Some(0.1));
+
+        // Clamped into the bounds when there is no initial value
+        assert_eq!(config.parameters["her_exchange_current"].initial, Some(1.0e-8));
+        assert_eq!(config.parameters["copper_loss_rate"].initial, Some(0.0));
+
+        // A fitted spectrum keeps the capacitances free
+        let mut config = FitConfig::default();
+        assert!(!config.fix_unused(&ModelOptions::default(), true).contains(&"positive_capacitance"));
+    }
+
+    #[test]
+    fn fixed_genes_never_change() {
+        let mut config = FitConfig::default();
+        config.genetic_algorithm.seed = Some(3);
+        config.genetic_algorithm.generations = 5;
+        config.fix("stack_resistance", 0.05).unwrap();
+        config.fix("rate_constant_positive", 2.0e-5).unwrap();
+        config.fix_unused(&ModelOptions::default(), false);
+
+        let fixed: Vec<(usize, f64)> = PARAMETER_KEYS.iter().enumerate()
+            .filter_map(|(index, key)| config.parameters[*key].initial.filter(|_| config.parameters[*key].fixed).map(|value| (index, value)))
+            .collect();
+        let assert_fixed = |population: &Population| {
+            for individual in &population.individual_list {
+                for &(index, value) in &fixed {
+                    assert_eq!(individual.genes[index], value, "{} changed", PARAMETER_KEYS[index]);
+                }
+            }
+        };
+
+        let mut population = config.population();
+        population.generate_pop(2000);
+        population.insert_initial_guess(&config.initial_guess());
+        assert_fixed(&population);
+
+        // Generations as in the fit, scored by a stand-in for the model so the search moves
+        for _ in 0..config.genetic_algorithm.generations {
+            for individual in &mut population.individual_list {
+                individual.fitness = individual.genes.iter().map(|gene| gene.abs().ln_1p()).sum();
+            }
+            population.preserve_best_solutions();
+            population.population_crossover();
+            population.mutate_population();
+            population.preserve_best_solutions();
+            population.current_generation += 1;
+
+            assert_fixed(&population);
+        }
+    }
+}
@@ -1,5 +1,53 @@
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::model_parameters::{Genes, GENE_COUNT, PARAMETER_LABELS};
+
+/// One candidate parameter set, the genes with the fitness they scored
+#[derive(Clone, Copy)]
+pub struct Individual {
+    pub genes: Genes,
+    pub fitness: f64,
+    /// Unique within the population
+    pub identifier: u64,
+}
+
+/// Space in which genes are sampled and mutated between their bounds
+#[derive(Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParameterScale {
+    /// Uniform in the value
+    #[default]
+    Linear,
+    /// Uniform in the logarithm, for parameters spanning decades
+    Log,
+}
+
+impl ParameterScale {
+    fn search_position(self, value: f64) -> f64 {
+        match self {
+            ParameterScale::Linear => value,
+            ParameterScale::Log => value.ln(),
+        }
+    }
+
+    fn search_value(self, position: f64) -> f64 {
+        match self {
+            ParameterScale::Linear => position,
+            ParameterScale::Log => position.exp(),
+        }
+    }
+
+    /// Progress table column: share of the upper bound, or for log genes the position between the bounds in decades (%)
+    fn bound_percentage(self, value: f64, lower: f64, upper: f64) -> f64 {
+        match self {
+            ParameterScale::Linear => value / upper * 100.0,
+            ParameterScale::Log => (value / lower).ln() / (upper / lower).ln() * 100.0,
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct Population {
@@ -12,54 +60,87 @@ pub struct Population {
     pub mutation_rate: f64,
     pub elite_size: f64,
 
-    pub individual_list: Vec<[f64; 12]>,
-    pub parameter_bounds_upper: [f64; 10],
-    pub parameter_bounds_lower: [f64; 10],
+    pub individual_list: Vec<Individual>,
+    pub parameter_bounds_upper: [f64; GENE_COUNT],
+    pub parameter_bounds_lower: [f64; GENE_COUNT],
+    pub parameter_scales: [ParameterScale; GENE_COUNT],
+    /// Value of each parameter held out of the search, None for the free ones
+    pub fixed_genes: [Option<f64>; GENE_COUNT],
 
     pub current_generation: u64,
-    pub maximum_generation: u64
+    pub maximum_generation: u64,
+
+    // Seeded for reproducible fits, the parallel mutation draws one seed per chunk from it
+    pub rng: StdRng,
 }
 
 impl Population {
     pub fn generate_pop(&mut self, pop_size: u64) {
         // Assign unique identifier to each individual for later multithreadings
-        let mut identifier = 0.0;
+        let mut identifier = 0;
 
         while self.individual_list.len() <= pop_size.try_into().unwrap() {
             let mut individual = self.random_population();
-            individual[11] = identifier;
+            individual.identifier = identifier;
             self.individual_list.push(individual);
 
-            identifier += 1.0;
+            identifier += 1;
         }
     }
 
-    fn random_population(&mut self) -> [f64; 12] {  // Changed return type from 11 to 12
-        let mut rng = rand::thread_rng();
-        let mut individual = [0.0; 12];  // Initialize with correct size
+    /// Place the initial guess in the first individual, genes without a guess keep their random value
+    pub fn insert_initial_guess(&mut self, initial_guess: &[Option<f64>; GENE_COUNT]) {
+        let Some(individual) = self.individual_list.first_mut() else {
+            return;
+        };
+
+        for (gene, guess) in individual.genes.iter_mut().zip(initial_guess) {
+            if let Some(guess) = guess {
+                *gene = *guess;
+            }
+        }
+    }
+
+    fn random_population(&mut self) -> Individual {
+        let mut genes = [0.0; GENE_COUNT];
 
         let mut index = 0;
         while index < self.parameter_bounds_upper.len() {
-            let new_gene = rng.gen_range(self.parameter_bounds_lower[index]..self.parameter_bounds_upper[index]);
-            individual[index] = new_gene;
+            let (lower, upper) = (self.parameter_bounds_lower[index], self.parameter_bounds_upper[index]);
+
+            let scale = self.parameter_scales[index];
+
+            // Inclusive, a free parameter may have equal bounds
+            let new_gene = match self.fixed_genes[index] {
+                Some(value) => value,
+                None => scale.search_value(self.rng.gen_range(scale.search_position(lower)..=scale.search_position(upper))).clamp(lower, upper),
+            };
+            genes[index] = new_gene;
             index += 1;
         }
-        
-        // Initialize fitness and identifier
-        individual[10] = f64::INFINITY;  // Fitness value
-        individual[11] = 0.0;            // Identifier
 
-        individual
+        // Not evaluated yet
+        Individual {
+            genes,
+            fitness: f64::INFINITY,
+            identifier: 0,
+        }
+    }
+
+    /// Indices of the genes the search runs over
+    fn free_genes(&self) -> Vec<usize> {
+        (0..GENE_COUNT).filter(|&index| self.fixed_genes[index].is_none()).collect()
     }
 
     pub fn population_crossover(&mut self) {
         let elite_indices = self.get_elite_indices();
+        let free_genes = self.free_genes();
         let (_, adaptive_crossover) = self.get_adaptive_rates();
         
         // Sort population by fitness
         let mut sorted_indices: Vec<(usize, f64)> = self.individual_list.iter()
             .enumerate()
-            .map(|(i, ind)| (i, ind[10]))
+            .map(|(i, ind)| (i, ind.fitness))
             .collect();
         
         sorted_indices.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
@@ -70,24 +151,25 @@ impl Population {
             .collect();
         
         for &elite_idx in &elite_indices {
-            if random_helper() < adaptive_crossover {
+            if self.rng.gen::<f64>() < adaptive_crossover {
                 // Select parent based on rank probability
-                let parent_idx = Self::select_by_rank(&weights);
+                let parent_idx = Self::select_by_rank(&weights, &mut self.rng);
                 let parent = self.individual_list[sorted_indices[parent_idx].0];
                 
-                // Perform crossover
-                for index in 0..10 {
-                    if random_helper() < 0.5 {
-                        self.individual_list[elite_idx][index] = parent[index];
+                // Perform crossover, fixed genes are the same in every individual.
+                // Genes are exchanged whole, so the exchange is the same on either scale
+                for &index in &free_genes {
+                    if self.rng.gen::<f64>() < 0.5 {
+                        self.individual_list[elite_idx].genes[index] = parent.genes[index];
                     }
                 }
             }
         }
     }
 
-    fn select_by_rank(weights: &[f64]) -> usize {
+    fn select_by_rank(weights: &[f64], rng: &mut StdRng) -> usize {
         let total: f64 = weights.iter().sum();
-        let mut r = random_helper() * total;
+        let mut r = rng.gen::<f64>() * total;
         
         for (i, &weight) in weights.iter().enumerate() {
             r -= weight;
@@ -115,15 +197,17 @@ impl Population {
         let elite_count = (self.individual_list.len() as f64 * self.elite_size) as usize;
         let (adaptive_mutation, _) = self.get_adaptive_rates();
         let best_fitness = self.best_fitness;
+        let generation_seed: u64 = self.rng.gen();
+        let free_genes = self.free_genes();
 
         // Use larger chunks for better parallel performance
-        self.individual_list[elite_count..].par_chunks_mut(1000).for_each(|chunk| {
-            let mut rng = rand::thread_rng(); // Create RNG per thread
+        self.individual_list[elite_count..].par_chunks_mut(1000).enumerate().for_each(|(chunk_index, chunk)| {
+            let mut rng = StdRng::seed_from_u64(generation_seed.wrapping_add(chunk_index as u64)); // Create RNG per chunk
             
             for individual in chunk {
                 if best_fitness.is_finite() {
-                    let fitness_factor = if individual[10].is_finite() {
-                        (individual[10] / best_fitness).min(2.0)
+                    let fitness_factor = if individual.fitness.is_finite() {
+                        (individual.fitness / best_fitness).min(2.0)
                     } else {
                         2.0
                     };
@@ -132,12 +216,15 @@ impl Population {
                     
                     // Batch random number generation
                     if rng.gen::<f64>() < adaptive_mutation {
-                        for index in 0..10 {
-                            let range = self.parameter_bounds_upper[index] - self.parameter_bounds_lower[index];
+                        for &index in &free_genes {
+                            let (lower, upper) = (self.parameter_bounds_lower[index], self.parameter_bounds_upper[index]);
+                            let scale = self.parameter_scales[index];
+
+                            // Noise proportional to the range in the gene's own scale, decades for log genes
+                            let range = scale.search_position(upper) - scale.search_position(lower);
                             let noise = intensity * range * (rng.gen::<f64>() * 2.0 - 1.0);
-                            individual[index] = (individual[index] + noise)
-                                .clamp(self.parameter_bounds_lower[index], 
-                                      self.parameter_bounds_upper[index]);
+                            individual.genes[index] = scale.search_value(scale.search_position(individual.genes[index]) + noise)
+                                .clamp(lower, upper);
                         }
                     }
                 }
@@ -148,14 +235,12 @@ impl Population {
     pub fn best_fitness_calc(&mut self) -> usize {
         let mut best_fitness = f64::INFINITY;
         let mut best_individual = 0;
-        let mut index = 0;
 
-        for individual in self.individual_list.iter() {
-            if individual[10] < best_fitness {  
-                best_fitness = individual[10];   
+        for (index, individual) in self.individual_list.iter().enumerate() {
+            if individual.fitness < best_fitness {  
+                best_fitness = individual.fitness;   
                 best_individual = index;
             }
-            index += 1;
         }
 
         self.best_fitness = best_fitness;
@@ -171,16 +256,16 @@ impl Population {
         println!("+----------------+-------------+-------------+");
         println!("| Parameter      | Value       | % of Upper  |");
         println!("+----------------+-------------+-------------+");
-        println!("| C1a (mol/m³)   | {:>11.2} | {:>11.2} |", self.individual_list[best_individual][0], (self.individual_list[best_individual][0] / self.parameter_bounds_upper[0]) * 100.0);
-        println!("| C2a (mol/m³)   | {:>11.2} | {:>11.2} |", self.individual_list[best_individual][8], (self.individual_list[best_individual][8] / self.parameter_bounds_upper[8]) * 100.0);
-        println!("| C0c (mol/m³)   | {:>11.2} | {:>11.2} |", self.individual_list[best_individual][9], (self.individual_list[best_individual][9] / self.parameter_bounds_upper[9]) * 100.0);
-        println!("| C1c (mol/m³)   | {:>11.2} | {:>11.2} |", self.individual_list[best_individual][1], (self.individual_list[best_individual][1] / self.parameter_bounds_upper[1]) * 100.0);
-        println!("| R (Ohm)        | {:>11.2} | {:>11.2} |", self.individual_list[best_individual][2], (self.individual_list[best_individual][2] / self.parameter_bounds_upper[2]) * 100.0);
-        println!("| k+ (1e-6 m/s)  | {:>11.2} | {:>11.2} |", self.individual_list[best_individual][3] / 1e-6, (self.individual_list[best_individual][3] / self.parameter_bounds_upper[3]) * 100.0);
-        println!("| k- (1e-6 m/s)  | {:>11.2} | {:>11.2} |", self.individual_list[best_individual][4] / 1e-6, (self.individual_list[best_individual][4] / self.parameter_bounds_upper[4]) * 100.0);
-        println!("| Dmem (1e-12)   | {:>11.2} | {:>11.2} |", self.individual_list[best_individual][5] / 1e-12, (self.individual_list[best_individual][5] / self.parameter_bounds_upper[5]) * 100.0);
-        println!("| Vc (V)         | {:>11.3} | {:>11.2} |", self.individual_list[best_individual][6], (self.individual_list[best_individual][6] / self.parameter_bounds_upper[6]) * 100.0);
-        println!("| Vd (V)         | {:>11.3} | {:>11.2} |", self.individual_list[best_individual][7], (self.individual_list[best_individual][7] / self.parameter_bounds_upper[7]) * 100.0);
+        let genes = self.individual_list[best_individual].genes;
+        for (index, (label, display_scale)) in PARAMETER_LABELS.iter().enumerate() {
+            match self.fixed_genes[index] {
+                Some(_) => println!("| {:<14} | {:>11.3} | {:>11} |", label, genes[index] / display_scale, "fixed"),
+                None => {
+                    let percentage = self.parameter_scales[index].bound_percentage(genes[index], self.parameter_bounds_lower[index], self.parameter_bounds_upper[index]);
+                    println!("| {:<14} | {:>11.3} | {:>11.2} |", label, genes[index] / display_scale, percentage)
+                }
+            }
+        }
         println!("+----------------+-------------+-------------+");
 
         best_individual
@@ -189,10 +274,10 @@ impl Population {
     fn get_elite_indices(&self) -> Vec<usize> {
         let elite_count = (self.individual_list.len() as f64 * self.elite_size) as usize;
         
-        // Create sorted indices using fitness value at index 10
+        // Create sorted indices using the fitness value
         let mut sorted_indices: Vec<(usize, f64)> = self.individual_list.iter()
             .enumerate()
-            .map(|(i, ind)| (i, ind[10])) // Changed from ind[8] to ind[10] for fitness value
+            .map(|(i, ind)| (i, ind.fitness))
             .collect();
         
         // Sort by fitness (lower is better) with NaN handling
@@ -220,9 +305,9 @@ impl Population {
         
         // Sort indices instead of moving whole arrays
         indices.sort_unstable_by(|&a, &b| {
-            match (self.individual_list[a][10].is_finite(), self.individual_list[b][10].is_finite()) {
-                (true, true) => self.individual_list[a][10]
-                    .partial_cmp(&self.individual_list[b][10])
+            match (self.individual_list[a].fitness.is_finite(), self.individual_list[b].fitness.is_finite()) {
+                (true, true) => self.individual_list[a].fitness
+                    .partial_cmp(&self.individual_list[b].fitness)
                     .unwrap_or(std::cmp::Ordering::Equal),
                 (true, false) => std::cmp::Ordering::Less,
                 (false, true) => std::cmp::Ordering::Greater,
@@ -231,8 +316,8 @@ impl Population {
         });
 
         // Update best fitness if valid
-        if self.individual_list[indices[0]][10].is_finite() {
-            self.best_fitness = self.individual_list[indices[0]][10];
+        if self.individual_list[indices[0]].fitness.is_finite() {
+            self.best_fitness = self.individual_list[indices[0]].fitness;
         }
 
         // Preserve elites more efficiently
@@ -258,7 +343,7 @@ impl Population {
         // Use parallel iterator for large populations
         let (sum, worst) = self.individual_list.par_iter()
             .map(|individual| {
-                let fitness = individual[10];
+                let fitness = individual.fitness;
                 if fitness.is_finite() {
                     (fitness, fitness)
                 } else {
@@ -275,7 +360,83 @@ impl Population {
     }
 }
 
-pub fn random_helper() -> f64 {
-    let mut rng = rand::thread_rng();
-    rng.gen_range(0.0..1.0)
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fit_config::FitConfig;
+    use crate::model_parameters::PARAMETER_KEYS;
+
+    fn seeded_population(size: u64) -> Population {
+        let mut config = FitConfig::default();
+        config.genetic_algorithm.seed = Some(7);
+
+        let mut population = config.population();
+        population.generate_pop(size);
+        population
+    }
+
+    fn assert_within_bounds(population: &Population) {
+        for individual in &population.individual_list {
+            for (index, gene) in individual.genes.iter().enumerate() {
+                let (lower, upper) = (population.parameter_bounds_lower[index], population.parameter_bounds_upper[index]);
+                assert!((lower..=upper).contains(gene), "{} = {} outside [{}, {}]", PARAMETER_KEYS[index], gene, lower, upper);
+            }
+        }
+    }
+
+    fn decade_counts(population: &Population, index: usize) -> Vec<usize> {
+        // Samples per decade between the bounds of a log gene
+        let lower = population.parameter_bounds_lower[index];
+        let decades = (population.parameter_bounds_upper[index] / lower).log10().round() as usize;
+
+        let mut counts = vec![0; decades];
+        for individual in &population.individual_list {
+            let decade = (individual.genes[index] / lower).log10().floor() as usize;
+            counts[decade.min(decades - 1)] += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn log_genes_are_sampled_evenly_across_decades() {
+        // rate_constant_positive spans the eight decades from 1e-8 to 1
+        let population = seeded_population(8000);
+        let index = PARAMETER_KEYS.iter().position(|key| *key == "rate_constant_positive").unwrap();
+        assert!(population.parameter_scales[index] == ParameterScale::Log);
+
+        assert_within_bounds(&population);
+        let counts = decade_counts(&population, index);
+        assert_eq!(counts.len(), 8);
+        for count in counts {
+            assert!((850..=1150).contains(&count), "{} samples in a decade, 1000 expected", count);
+        }
+    }
+
+    #[test]
+    fn mutation_keeps_log_genes_within_bounds_and_spread() {
+        let mut population = seeded_population(8000);
+        for (rank, individual) in population.individual_list.iter_mut().enumerate() {
+            individual.fitness = 1.0 + rank as f64;
+        }
+        population.best_fitness = 1.0;
+        population.mutation_rate = 1.0;
+
+        let index = PARAMETER_KEYS.iter().position(|key| *key == "diffusion_coefficient").unwrap();
+        let before = population.individual_list[5000].genes[index];
+        population.mutate_population();
+
+        assert_within_bounds(&population);
+        assert_ne!(population.individual_list[5000].genes[index], before);
+        // Noise is uniform in decades, so no decade empties out or takes the whole population
+        for count in decade_counts(&population, index) {
+            assert!((1000..=3000).contains(&count), "{} of 8000 samples in a decade", count);
+        }
+    }
+
+    #[test]
+    fn log_genes_report_their_position_in_decades() {
+        let percentage = ParameterScale::Log.bound_percentage(1.0e-4, 1.0e-8, 1.0);
+        assert!((percentage - 50.0).abs() < 1.0e-9);
+        assert!((ParameterScale::Linear.bound_percentage(0.25, 0.0, 0.5) - 50.0).abs() < 1.0e-9);
+    }
+}
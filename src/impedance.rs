@@ -0,0 +1,190 @@
+// Small-signal impedance of the stack around a DC operating point, and measured spectra for fitting.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::ops::{Add, Div, Mul};
+
+use csv::Reader;
+use serde::Deserialize;
+
+use crate::electrochem_model::{check_operating_point, ElectrochemicalModel, ModelOptions};
+use crate::model_parameters::ModelParameters;
+use crate::stack_config::StackConfig;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex {
+    pub fn new(re: f64, im: f64) -> Self {
+        Complex { re, im }
+    }
+
+    pub fn real(re: f64) -> Self {
+        Complex { re, im: 0.0 }
+    }
+
+    pub fn norm_squared(&self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+
+    pub fn inverse(&self) -> Self {
+        let norm = self.norm_squared();
+        Complex::new(self.re / norm, -self.im / norm)
+    }
+
+    pub fn exp(&self) -> Self {
+        let magnitude = self.re.exp();
+        Complex::new(magnitude * self.im.cos(), magnitude * self.im.sin())
+    }
+
+    /// Principal square root
+    pub fn sqrt(&self) -> Self {
+        let modulus = self.norm_squared().sqrt();
+        let re = ((modulus + self.re) / 2.0).sqrt();
+        let im = ((modulus - self.re) / 2.0).sqrt();
+
+        Complex::new(re, if self.im < 0.0 { -im } else { im })
+    }
+
+    pub fn tanh(&self) -> Self {
+        // Written with exp(-2z) so large arguments tend to one instead of overflowing
+        if self.re >= 0.0 {
+            let decay = Complex::new(-2.0 * self.re, -2.0 * self.im).exp();
+            (Complex::real(1.0) + decay * -1.0) / (Complex::real(1.0) + decay)
+        } else {
+            Complex::new(-self.re, -self.im).tanh() * -1.0
+        }
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+
+    fn add(self, other: Complex) -> Complex {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+
+    fn mul(self, other: Complex) -> Complex {
+        Complex::new(self.re * other.re - self.im * other.im, self.re * other.im + self.im * other.re)
+    }
+}
+
+impl Mul<f64> for Complex {
+    type Output = Complex;
+
+    fn mul(self, scale: f64) -> Complex {
+        Complex::new(self.re * scale, self.im * scale)
+    }
+}
+
+impl Div for Complex {
+    type Output = Complex;
+
+    #[allow(clippy::suspicious_arithmetic_impl)]
+    fn div(self, other: Complex) -> Complex {
+        self * other.inverse()
+    }
+}
+
+/// Condition the spectrum is taken at, current positive on charge
+#[derive(Clone, Copy, Deserialize)]
+pub struct OperatingPoint {
+    pub state_of_charge: f32,
+    pub temperature: f32,
+    #[serde(default)]
+    pub current: f32,
+}
+
+/// Stack impedance (Ohm) at each frequency (Hz)
+pub fn impedance_spectrum(parameters: &ModelParameters, options: &ModelOptions, stack: &StackConfig, operating_point: &OperatingPoint, frequencies: &[f64]) -> Vec<Complex> {
+    let mut model = ElectrochemicalModel::new(parameters, options, stack);
+    model.set_operating_point(operating_point.state_of_charge, operating_point.temperature, operating_point.current);
+
+    frequencies.iter().map(|&frequency| model.impedance(frequency)).collect()
+}
+
+/// Logarithmically spaced frequencies from minimum to maximum (Hz)
+pub fn log_frequencies(minimum: f64, maximum: f64, per_decade: usize) -> Vec<f64> {
+    let decades = (maximum / minimum).log10();
+    let count = (decades * per_decade as f64).round() as usize + 1;
+
+    (0..count).map(|index| minimum * 10.0_f64.powf(decades * index as f64 / (count - 1).max(1) as f64)).collect()
+}
+
+pub fn write_spectrum(path: &str, frequencies: &[f64], impedance: &[Complex]) -> Result<(), Box<dyn Error>> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "Frequency (Hz), Z real (Ohm), Z imag (Ohm)")?;
+
+    for (frequency, point) in frequencies.iter().zip(impedance) {
+        writeln!(writer, "{}, {}, {}", frequency, point.re, point.im)?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct MeasuredImpedanceConfig {
+    data: String,
+    operating_point: OperatingPoint,
+    #[serde(default = "default_weight")]
+    weight: f64,
+}
+
+fn default_weight() -> f64 {
+    1.0
+}
+
+/// Measured spectrum added to the fit, read from a JSON file naming the CSV data and its operating point
+pub struct MeasuredImpedance {
+    pub operating_point: OperatingPoint,
+    pub frequencies: Vec<f64>,
+    pub impedance: Vec<Complex>,
+    /// Scales the impedance error against the voltage error of the fit
+    pub weight: f64,
+}
+
+impl MeasuredImpedance {
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let config: MeasuredImpedanceConfig = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        check_operating_point(config.operating_point.state_of_charge, config.operating_point.temperature)?;
+
+        // Frequency (Hz), Z real (Ohm), Z imag (Ohm) with a header row
+        let mut frequencies = Vec::new();
+        let mut impedance = Vec::new();
+
+        let mut reader = Reader::from_path(&config.data)?;
+        for result in reader.records() {
+            let record = result?;
+
+            frequencies.push(record[0].trim().parse()?);
+            impedance.push(Complex::new(record[1].trim().parse()?, record[2].trim().parse()?));
+        }
+
+        Ok(MeasuredImpedance {
+            operating_point: config.operating_point,
+            frequencies,
+            impedance,
+            weight: config.weight,
+        })
+    }
+
+    /// Weighted sum of squared errors relative to the measured magnitude, so every frequency counts alike
+    pub fn fitness(&self, parameters: &ModelParameters, options: &ModelOptions, stack: &StackConfig) -> f64 {
+        let simulated = impedance_spectrum(parameters, options, stack, &self.operating_point, &self.frequencies);
+
+        let error: f64 = simulated.iter().zip(&self.impedance)
+            .map(|(simulated, measured)| (*simulated + *measured * -1.0).norm_squared() / measured.norm_squared())
+            .sum();
+
+        self.weight * error
+    }
+}
@@ -0,0 +1,399 @@
+// Time integration of the model state over one data interval.
+// The model exposes its state as a flat vector and the rates of change at that state,
+// the integrators only ever see those vectors.
+
+use serde::{Deserialize, Serialize};
+
+const NEWTON_ITERATIONS: usize = 10;
+const BDF_RELATIVE_TOLERANCE: f64 = 1.0e-6;
+const BDF_ABSOLUTE_TOLERANCE: f64 = 1.0e-9;
+const MINIMUM_STEP_FRACTION: f64 = 1.0e-6;
+
+/// A set of first order ODEs dy/dt = f(y), the inputs are held constant over the interval
+pub trait OdeSystem {
+    fn state(&self) -> Vec<f64>;
+    fn set_state(&mut self, state: &[f64]);
+    /// Rates of change at the current state
+    fn derivative(&self) -> Vec<f64>;
+}
+
+/// Integration method used for every model step
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum Integrator {
+    /// Explicit Euler with a fixed number of sub-steps per interval
+    Euler { substeps: u32 },
+    /// Classic fourth order Runge-Kutta with a fixed number of sub-steps per interval
+    #[serde(rename = "rk4")]
+    RungeKutta4 { substeps: u32 },
+    /// Dormand-Prince 5(4) with the step size adapted to the tolerances
+    #[serde(rename = "rk45")]
+    RungeKutta45 { relative_tolerance: f64, absolute_tolerance: f64 },
+    /// Implicit second order backward differentiation for stiff settings, fixed sub-steps
+    Bdf { substeps: u32 },
+}
+
+impl Default for Integrator {
+    fn default() -> Self {
+        Integrator::Euler { substeps: 1 }
+    }
+}
+
+/// Advance the system over the interval (s), leaving it at the final state
+pub fn integrate<S: OdeSystem>(system: &mut S, integrator: Integrator, interval: f64) {
+    let state = system.state();
+
+    let final_state = match integrator {
+        Integrator::Euler { substeps } => euler(system, state, interval, substeps.max(1)),
+        Integrator::RungeKutta4 { substeps } => runge_kutta_4(system, state, interval, substeps.max(1)),
+        Integrator::RungeKutta45 { relative_tolerance, absolute_tolerance } => {
+            runge_kutta_45(system, state, interval, relative_tolerance, absolute_tolerance)
+        }
+        Integrator::Bdf { substeps } => bdf(system, state, interval, substeps.max(1)),
+    };
+
+    system.set_state(&final_state);
+}
+
+fn rate_at<S: OdeSystem>(system: &mut S, state: &[f64]) -> Vec<f64> {
+    system.set_state(state);
+    system.derivative()
+}
+
+fn offset(state: &[f64], stages: &[(f64, &[f64])]) -> Vec<f64> {
+    // state + Σ weight * rate
+    let mut result = state.to_vec();
+    for (weight, rate) in stages {
+        for (value, rate) in result.iter_mut().zip(rate.iter()) {
+            *value += weight * rate;
+        }
+    }
+
+    result
+}
+
+fn euler<S: OdeSystem>(system: &mut S, mut state: Vec<f64>, interval: f64, substeps: u32) -> Vec<f64> {
+    let step = interval / substeps as f64;
+
+    for _ in 0..substeps {
+        let rate = rate_at(system, &state);
+        state = offset(&state, &[(step, &rate)]);
+    }
+
+    state
+}
+
+fn runge_kutta_4<S: OdeSystem>(system: &mut S, mut state: Vec<f64>, interval: f64, substeps: u32) -> Vec<f64> {
+    let step = interval / substeps as f64;
+
+    for _ in 0..substeps {
+        let k1 = rate_at(system, &state);
+        let k2 = rate_at(system, &offset(&state, &[(step / 2.0, &k1)]));
+        let k3 = rate_at(system, &offset(&state, &[(step / 2.0, &k2)]));
+        let k4 = rate_at(system, &offset(&state, &[(step, &k3)]));
+
+        state = offset(&state, &[(step / 6.0, &k1), (step / 3.0, &k2), (step / 3.0, &k3), (step / 6.0, &k4)]);
+    }
+
+    state
+}
+
+fn runge_kutta_45<S: OdeSystem>(system: &mut S, mut state: Vec<f64>, interval: f64, relative_tolerance: f64, absolute_tolerance: f64) -> Vec<f64> {
+    // Dormand-Prince coefficients, the system is autonomous so the stage times are not needed
+    const A: [&[f64]; 6] = [
+        &[1.0 / 5.0],
+        &[3.0 / 40.0, 9.0 / 40.0],
+        &[44.0 / 45.0, -56.0 / 15.0, 32.0 / 9.0],
+        &[19372.0 / 6561.0, -25360.0 / 2187.0, 64448.0 / 6561.0, -212.0 / 729.0],
+        &[9017.0 / 3168.0, -355.0 / 33.0, 46732.0 / 5247.0, 49.0 / 176.0, -5103.0 / 18656.0],
+        &[35.0 / 384.0, 0.0, 500.0 / 1113.0, 125.0 / 192.0, -2187.0 / 6784.0, 11.0 / 84.0],
+    ];
+    // Difference between the fifth and fourth order weights
+    const ERROR: [f64; 7] = [71.0 / 57600.0, 0.0, -71.0 / 16695.0, 71.0 / 1920.0, -17253.0 / 339200.0, 22.0 / 525.0, -1.0 / 40.0];
+
+    if !state.iter().all(|value| value.is_finite()) {
+        return state;
+    }
+
+    let minimum_step = interval * MINIMUM_STEP_FRACTION;
+    let mut time = 0.0;
+    let mut step = interval;
+
+    while time < interval {
+        step = step.min(interval - time);
+
+        let mut stages: Vec<Vec<f64>> = vec![rate_at(system, &state)];
+        for coefficients in A {
+            let weighted: Vec<(f64, &[f64])> = coefficients.iter().zip(&stages).map(|(a, k)| (step * a, k.as_slice())).collect();
+            let stage_state = offset(&state, &weighted);
+            stages.push(rate_at(system, &stage_state));
+        }
+
+        // The last stage is evaluated at the fifth order solution
+        let weighted: Vec<(f64, &[f64])> = A[5].iter().zip(&stages).map(|(a, k)| (step * a, k.as_slice())).collect();
+        let candidate = offset(&state, &weighted);
+
+        // A step through an undefined state is rejected, one that cannot be made smaller ends the integration
+        if !stages.iter().flatten().chain(&candidate).all(|value| value.is_finite()) {
+            if step <= minimum_step {
+                return vec![f64::NAN; state.len()];
+            }
+            step = (step * 0.2).max(minimum_step);
+            continue;
+        }
+
+        let mut error: f64 = 0.0;
+        for index in 0..state.len() {
+            let local_error: f64 = ERROR.iter().zip(&stages).map(|(e, k)| step * e * k[index]).sum();
+            let scale = absolute_tolerance + relative_tolerance * state[index].abs().max(candidate[index].abs());
+            error = error.max(local_error.abs() / scale);
+        }
+
+        if error <= 1.0 || step <= minimum_step {
+            time += step;
+            state = candidate;
+        }
+
+        // Standard step size controller with a safety factor
+        let factor = if error > 0.0 { 0.9 * error.powf(-0.2) } else { 5.0 };
+        step = (step * factor.clamp(0.2, 5.0)).max(minimum_step);
+    }
+
+    state
+}
+
+fn bdf<S: OdeSystem>(system: &mut S, mut state: Vec<f64>, interval: f64, substeps: u32) -> Vec<f64> {
+    let step = interval / substeps as f64;
+    let mut previous: Option<Vec<f64>> = None;
+
+    for _ in 0..substeps {
+        // BDF1 (backward Euler) to start, BDF2 once there is a history
+        //   y(n+1) = history + gain * h * f(y(n+1))
+        let (history, gain) = match &previous {
+            Some(previous) => (offset(&state, &[(1.0 / 3.0, &state), (-1.0 / 3.0, previous)]), 2.0 / 3.0),
+            None => (state.clone(), 1.0),
+        };
+
+        let next = newton_solve(system, &state, &history, gain * step);
+
+        previous = Some(state);
+        state = next;
+    }
+
+    state
+}
+
+fn newton_solve<S: OdeSystem>(system: &mut S, guess: &[f64], history: &[f64], scaled_step: f64) -> Vec<f64> {
+    // Simplified Newton on G(y) = y - history - scaled_step * f(y), Jacobian frozen at the guess
+    let size = guess.len();
+    let rate = rate_at(system, guess);
+    let jacobian = finite_difference_jacobian(system, guess, &rate);
+
+    let mut matrix = vec![vec![0.0; size]; size];
+    for row in 0..size {
+        for column in 0..size {
+            matrix[row][column] = -scaled_step * jacobian[row][column];
+        }
+        matrix[row][row] += 1.0;
+    }
+
+    let mut state = guess.to_vec();
+    let mut rate = rate;
+
+    for _ in 0..NEWTON_ITERATIONS {
+        let residual: Vec<f64> = (0..size).map(|index| state[index] - history[index] - scaled_step * rate[index]).collect();
+        let Some(correction) = solve_linear(matrix.clone(), residual) else {
+            break;
+        };
+
+        let mut converged = true;
+        for index in 0..size {
+            state[index] -= correction[index];
+            if correction[index].abs() > BDF_ABSOLUTE_TOLERANCE + BDF_RELATIVE_TOLERANCE * state[index].abs() {
+                converged = false;
+            }
+        }
+
+        rate = rate_at(system, &state);
+        if converged {
+            break;
+        }
+    }
+
+    state
+}
+
+fn finite_difference_jacobian<S: OdeSystem>(system: &mut S, state: &[f64], rate: &[f64]) -> Vec<Vec<f64>> {
+    // Forward differences, column by column, perturbations sized for the model's single precision
+    let size = state.len();
+    let mut jacobian = vec![vec![0.0; size]; size];
+    let mut perturbed = state.to_vec();
+
+    for column in 0..size {
+        let perturbation = 1.0e-3 * state[column].abs().max(1.0e-3);
+        perturbed[column] = state[column] + perturbation;
+
+        let perturbed_rate = rate_at(system, &perturbed);
+        for row in 0..size {
+            jacobian[row][column] = (perturbed_rate[row] - rate[row]) / perturbation;
+        }
+
+        perturbed[column] = state[column];
+    }
+
+    jacobian
+}
+
+#[allow(clippy::needless_range_loop)]
+fn solve_linear(mut matrix: Vec<Vec<f64>>, mut vector: Vec<f64>) -> Option<Vec<f64>> {
+    // Gaussian elimination with partial pivoting
+    let size = vector.len();
+
+    for pivot in 0..size {
+        let best = (pivot..size).max_by(|&a, &b| matrix[a][pivot].abs().total_cmp(&matrix[b][pivot].abs()))?;
+        if matrix[best][pivot] == 0.0 {
+            return None;
+        }
+        matrix.swap(pivot, best);
+        vector.swap(pivot, best);
+
+        for row in pivot + 1..size {
+            let factor = matrix[row][pivot] / matrix[pivot][pivot];
+            if factor == 0.0 {
+                continue;
+            }
+            for column in pivot..size {
+                matrix[row][column] -= factor * matrix[pivot][column];
+            }
+            vector[row] -= factor * vector[pivot];
+        }
+    }
+
+    let mut solution = vec![0.0; size];
+    for row in (0..size).rev() {
+        let known: f64 = (row + 1..size).map(|column| matrix[row][column] * solution[column]).sum();
+        solution[row] = (vector[row] - known) / matrix[row][row];
+    }
+
+    Some(solution)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// dy/dt = A y for a constant matrix A
+    struct Linear {
+        matrix: Vec<Vec<f64>>,
+        state: Vec<f64>,
+    }
+
+    impl OdeSystem for Linear {
+        fn state(&self) -> Vec<f64> {
+            self.state.clone()
+        }
+
+        fn set_state(&mut self, state: &[f64]) {
+            self.state = state.to_vec();
+        }
+
+        fn derivative(&self) -> Vec<f64> {
+            self.matrix.iter().map(|row| row.iter().zip(&self.state).map(|(a, y)| a * y).sum()).collect()
+        }
+    }
+
+    fn decay_error(integrator: Integrator) -> f64 {
+        // y' = -k y from y(0) = 1 over one second, y(1) = exp(-k)
+        let rate_constant = 2.0;
+        let mut system = Linear { matrix: vec![vec![-rate_constant]], state: vec![1.0] };
+        integrate(&mut system, integrator, 1.0);
+
+        (system.state[0] - (-rate_constant).exp()).abs()
+    }
+
+    fn observed_order(coarse: Integrator, fine: Integrator) -> f64 {
+        // The fine integrator takes twice the sub-steps of the coarse one
+        (decay_error(coarse) / decay_error(fine)).log2()
+    }
+
+    #[test]
+    fn euler_is_first_order() {
+        let order = observed_order(Integrator::Euler { substeps: 50 }, Integrator::Euler { substeps: 100 });
+        assert!((order - 1.0).abs() < 0.1, "order {}", order);
+    }
+
+    #[test]
+    fn runge_kutta_4_is_fourth_order() {
+        let order = observed_order(Integrator::RungeKutta4 { substeps: 10 }, Integrator::RungeKutta4 { substeps: 20 });
+        assert!((order - 4.0).abs() < 0.2, "order {}", order);
+    }
+
+    #[test]
+    fn bdf_is_second_order() {
+        let order = observed_order(Integrator::Bdf { substeps: 50 }, Integrator::Bdf { substeps: 100 });
+        assert!((order - 2.0).abs() < 0.2, "order {}", order);
+    }
+
+    #[test]
+    fn runge_kutta_45_meets_its_tolerance() {
+        let error = decay_error(Integrator::RungeKutta45 { relative_tolerance: 1.0e-8, absolute_tolerance: 1.0e-10 });
+        assert!(error < 1.0e-7, "error {}", error);
+    }
+
+    /// dy/dt = -1 down to y = 0.5, undefined below, counting the evaluations
+    struct Undefined {
+        state: Vec<f64>,
+        evaluations: std::cell::Cell<usize>,
+    }
+
+    impl OdeSystem for Undefined {
+        fn state(&self) -> Vec<f64> {
+            self.state.clone()
+        }
+
+        fn set_state(&mut self, state: &[f64]) {
+            self.state = state.to_vec();
+        }
+
+        fn derivative(&self) -> Vec<f64> {
+            self.evaluations.set(self.evaluations.get() + 1);
+            vec![if self.state[0] >= 0.5 { -1.0 } else { f64::NAN }]
+        }
+    }
+
+    #[test]
+    fn runge_kutta_45_gives_up_on_an_undefined_state() {
+        let integrator = Integrator::RungeKutta45 { relative_tolerance: 1.0e-6, absolute_tolerance: 1.0e-9 };
+        let mut system = Undefined { state: vec![1.0], evaluations: std::cell::Cell::new(0) };
+
+        integrate(&mut system, integrator, 1.0);
+        assert!(system.state[0].is_nan());
+        assert!(system.evaluations.get() < 10_000, "{} evaluations", system.evaluations.get());
+
+        // Later intervals start from the undefined state and return at once
+        let evaluations = system.evaluations.get();
+        integrate(&mut system, integrator, 1.0);
+        assert!(system.state[0].is_nan());
+        assert_eq!(system.evaluations.get(), evaluations);
+    }
+
+    #[test]
+    fn bdf_is_stable_on_a_stiff_system() {
+        // y1 relaxes onto y2 a thousand times faster than y2 decays,
+        //   y1(t) = c exp(-t) - c exp(-1000 t) and y2(t) = exp(-t) with c = 1000/999
+        let stiff = || Linear { matrix: vec![vec![-1000.0, 1000.0], vec![0.0, -1.0]], state: vec![0.0, 1.0] };
+        let coefficient = 1000.0 / 999.0;
+        let exact = [coefficient * (-1.0f64).exp(), (-1.0f64).exp()];
+
+        // Steps a hundred times the fast time constant, far outside the explicit stability limit
+        let mut system = stiff();
+        integrate(&mut system, Integrator::Bdf { substeps: 10 }, 1.0);
+        for (value, exact) in system.state.iter().zip(exact) {
+            assert!((value - exact).abs() < 0.01 * exact, "{} against {}", value, exact);
+        }
+
+        let mut system = stiff();
+        integrate(&mut system, Integrator::Euler { substeps: 10 }, 1.0);
+        assert!(system.state[0].abs() > 1.0e6);
+    }
+}
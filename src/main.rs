@@ -1,90 +1,422 @@
+mod cycle_metrics;
 mod data_preparation;
 mod electrochem_model;
+mod fit_config;
 mod genetic_algorithm;
+mod impedance;
+mod integrator;
+mod model_parameters;
+mod polarization;
+mod protocol;
+mod shunt;
+mod speciation;
+mod stack_config;
 
-use electrochem_model::electrochem_model_sim;
+use electrochem_model::{check_operating_point, electrochem_model_sim};
 use crate::data_preparation::process_data;
-use crate::genetic_algorithm::Population;
+use crate::fit_config::FitConfig;
+use crate::impedance::{impedance_spectrum, log_frequencies, write_spectrum, MeasuredImpedance, OperatingPoint};
+use crate::model_parameters::{ModelParameters, PARAMETER_KEYS, PARAMETER_NAMES};
+use crate::polarization::{current_sweep, polarization_curve, write_polarization_curve};
+use crate::protocol::{read_protocol, run_protocol};
+use crate::stack_config::StackConfig;
+use clap::{Args, Parser, Subcommand};
+use serde::{Deserialize, Serialize};
 use std::time::{SystemTime, UNIX_EPOCH};
 use std::sync::{Arc, Mutex};
 use rayon::prelude::*;
 use csv::Reader;
+use std::error::Error;
+use std::fs::File;
+use std::path::Path;
 
-fn read_real_data() -> (Vec<f32>, Vec<f32>) {
+/// Electrochemical model of a copper redox flow battery stack, fitted to cycling data
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Convert the cycler export into the time, voltage and current file used by the model
+    Prepare {
+        #[arg(long, default_value = "input.csv")]
+        input: String,
+        #[arg(long, default_value = "data.csv")]
+        output: String,
+    },
+    /// Fit the model parameters to measured data with the genetic algorithm
+    Fit(FitArgs),
+    /// Simulate the measured data with one parameter set
+    Simulate {
+        #[arg(long, default_value = "data.csv")]
+        data: String,
+        #[command(flatten)]
+        model: ModelArgs,
+        #[arg(long, default_value = ".")]
+        output_dir: String,
+    },
+    /// Print the parameters and per-cycle metrics of a finished fit or simulation
+    Report {
+        /// Directory holding cycle_summary.csv
+        #[arg(long, default_value = ".")]
+        results: String,
+        /// Parameter set, best_parameters.json in the results directory by default
+        #[arg(long)]
+        parameters: Option<String>,
+    },
+    /// Simulate a cycling protocol with a fitted parameter set
+    Protocol {
+        protocol: String,
+        #[command(flatten)]
+        model: ModelArgs,
+        #[arg(long, default_value = ".")]
+        output_dir: String,
+    },
+    /// Impedance spectrum from 1 mHz to 10 kHz at a state of charge
+    Impedance {
+        /// State of charge, between 0 and 1 exclusive
+        soc: f32,
+        /// DC current (A), positive on charge
+        #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+        current: f32,
+        /// Stack temperature (K), the ambient temperature of the stack by default
+        #[arg(long)]
+        temperature: Option<f32>,
+        #[command(flatten)]
+        model: ModelArgs,
+        #[arg(long, default_value = ".")]
+        output_dir: String,
+    },
+    /// Polarization curve from discharge to charge at a state of charge
+    Polarization {
+        /// State of charge, between 0 and 1 exclusive
+        soc: f32,
+        /// Current magnitude at both ends of the sweep (A)
+        #[arg(long, default_value_t = 100.0)]
+        maximum_current: f32,
+        /// Stack temperature (K), the ambient temperature of the stack by default
+        #[arg(long)]
+        temperature: Option<f32>,
+        #[command(flatten)]
+        model: ModelArgs,
+        #[arg(long, default_value = ".")]
+        output_dir: String,
+    },
+}
+
+/// Parameter set and stack a simulation runs with
+#[derive(Args)]
+struct ModelArgs {
+    #[arg(long, default_value = "best_parameters.json")]
+    parameters: String,
+    #[arg(long, default_value = "stack.json")]
+    stack: String,
+}
+
+/// Settings of a fit, the flags override the config file
+#[derive(Args)]
+struct FitArgs {
+    /// Parameter bounds, genetic algorithm settings and file paths
+    #[arg(long, default_value = "fit.json")]
+    config: String,
+    #[arg(long)]
+    data: Option<String>,
+    #[arg(long)]
+    stack: Option<String>,
+    /// Measured impedance spectrum added to the fitness when the file exists
+    #[arg(long)]
+    eis: Option<String>,
+    /// Directory for the fitted parameters, the saved config and the simulation of the best individual
+    #[arg(long)]
+    output_dir: Option<String>,
+    #[arg(long)]
+    population: Option<u64>,
+    #[arg(long)]
+    generations: Option<u64>,
+    /// Seed of the genetic algorithm, random when neither given nor in the config
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Hold a parameter at a value instead of fitting it, repeatable
+    #[arg(long, value_name = "NAME=VALUE", value_parser = parse_fixed_parameter)]
+    fix: Vec<(String, f64)>,
+    /// Fit a parameter the config holds fixed, repeatable
+    #[arg(long, value_name = "NAME")]
+    free: Vec<String>,
+}
+
+fn parse_fixed_parameter(argument: &str) -> Result<(String, f64), String> {
+    let (name, value) = argument.split_once('=').ok_or_else(|| format!("expected NAME=VALUE, found {}", argument))?;
+    let value = value.parse::<f64>().map_err(|e| format!("{}: {}", value, e))?;
+
+    Ok((name.to_string(), value))
+}
+
+// Time (s), current (A) and voltage (V) of every logged sample
+type RealData = (Vec<f32>, Vec<f32>, Vec<f32>);
+
+fn read_real_data(path: &str) -> Result<RealData, Box<dyn Error>> {
     // Import real data to use in the model
+    let mut real_time: Vec<f32> = Vec::new();
     let mut real_current: Vec<f32> = Vec::new();
     let mut real_voltage: Vec<f32> = Vec::new();
 
-    let mut rdr = Reader::from_path("data.csv").unwrap();
+    let mut rdr = Reader::from_path(path).map_err(|e| format!("{}: {}", path, e))?;
 
     for result in rdr.records() {
-        let record = result.unwrap();
+        let record = result?;
 
-        real_current.push(record[2].parse::<f32>().unwrap());
-        real_voltage.push(record[1].parse::<f32>().unwrap());  
+        real_time.push(record[0].parse::<f32>()?);
+        real_current.push(record[2].parse::<f32>()?);
+        real_voltage.push(record[1].parse::<f32>()?);
     }
 
-    return (real_current, real_voltage)
+    Ok((real_time, real_current, real_voltage))
 }
 
-fn main() {
-    // Prepare data
-    let input_file = "input.csv";
-    let output_file = "data.csv";  // This will be used by the main program
+/// Contents of best_parameters.json, the fitness of the fit that produced the parameters alongside them
+#[derive(Serialize, Deserialize)]
+struct ParameterFile {
+    fitness: Option<f64>,
+    /// Parameters held at their value rather than fitted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    fixed: Vec<String>,
+    #[serde(flatten)]
+    parameters: ModelParameters,
+}
+
+fn write_parameters(path: &Path, parameter_file: &ParameterFile) -> Result<(), Box<dyn Error>> {
+    serde_json::to_writer_pretty(File::create(path)?, parameter_file)?;
+    Ok(())
+}
+
+fn read_parameters(path: &Path) -> Result<ParameterFile, Box<dyn Error>> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let parameters = serde_json::from_reader(file).map_err(|e| format!("{}: {}", path.display(), e))?;
 
-    match process_data(input_file, output_file) {
-        Ok(_) => println!("Successfully processed {} into {}", input_file, output_file),
-        Err(e) => eprintln!("Error processing data: {}", e),
+    Ok(parameters)
+}
+
+fn load_stack_config(path: &str) -> Result<StackConfig, Box<dyn Error>> {
+    // Fall back to the built-in pilot stack when no config file is present
+    if !std::path::Path::new(path).exists() {
+        println!("No {} found, using the default {}-cell stack", path, StackConfig::default().cells);
+        return Ok(StackConfig::default());
     }
 
-    // Initialize population
-    let mut population = Population {
-        // Fitness tracking variables
-        best_fitness: 0.0,          // Best fitness found so far
-        worst_fitness: 0.0,         // Worst fitness in current population
-        average_fitness: 0.0,       // Average fitness of current population
-
-        // Genetic algorithm parameters
-        mutation_intensity: 0.3,   // Reduced for finer local search
-        crossover_rate: 0.7,        // Increased for better gene mixing
-        mutation_rate: 0.3,         // Balanced for exploration/exploitation
-        elite_size: 0.1,           // Increased elite preservation
-
-        individual_list: Vec::new(),
-
-        // Model parameter bounds
-        parameter_bounds_upper: [
-            3000.0,         // [0] Anolyte concentration C1 (mol/m³)
-            3000.0,         // [1] Catholyte concentration C1 (mol/m³)
-            0.5,            // [2] Stack resistance (Ohm)
-            1.0e0,          // [3] Positive rate constant k+ (m/s)
-            1.0e0,          // [4] Negative rate constant k- (m/s)
-            1.0e-10,        // [5] Membrane diffusion coefficient (m²/s)
-            0.5,            // [6] Charge offset (V)
-            0.5,            // [7] Discharge offset (V)
-            500.0,          // [8] Anolyte concentration C2 (mol/m³)
-            500.0,          // [9] Catholyte concentration C0 (mol/m³)
-        ],
-        parameter_bounds_lower: [
-            1000.0,         // [0] Anolyte concentration C1 (mol/m³)
-            1000.0,         // [1] Catholyte concentration C1 (mol/m³)
-            0.0,            // [2] Stack resistance (Ohm)
-            1.0e-8,         // [3] Positive rate constant k+ (m/s)
-            1.0e-8,         // [4] Negative rate constant k- (m/s)
-            1.0e-14,        // [5] Membrane diffusion coefficient (m²/s)
-            -0.5,           // [6] Charge offset (V)
-            -0.5,           // [7] Discharge offset (V)
-            0.0,            // [8] Anolyte concentration C2 (mol/m³)
-            0.0,            // [9] Catholyte concentration C0 (mol/m³)
-        ],
-
-        // Algorithm control
-        maximum_generation: 150,         // More generations for better convergence
-        current_generation: 0,          // Current generation counter
+    let stack = StackConfig::load(path).map_err(|e| format!("{}: {}", path, e))?;
+    println!("Loaded {}-cell stack from {}", stack.cells, path);
+
+    Ok(stack)
+}
+
+fn simulate_data(data_file: &str, model: &ModelArgs, output_dir: &str) -> Result<(), Box<dyn Error>> {
+    // Replay the measured current with a previously fitted parameter set
+    let parameters = read_parameters(Path::new(&model.parameters))?.parameters;
+    let stack = load_stack_config(&model.stack)?;
+    let (real_time, real_current, real_voltage) = read_real_data(data_file)?;
+
+    let output_dir = Path::new(output_dir);
+    std::fs::create_dir_all(output_dir)?;
+
+    let fitness = electrochem_model_sim(Some(output_dir), &parameters, &stack.options, &stack, real_time, real_current, real_voltage);
+    println!("Simulated {} into {}, fitness {:.2}", data_file, output_dir.display(), fitness / 10000.0);
+
+    Ok(())
+}
+
+fn print_report(results: &str, parameter_file: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let results = Path::new(results);
+    let parameter_file = parameter_file.map_or_else(|| results.join("best_parameters.json"), |file| Path::new(file).to_path_buf());
+    let parameter_file = read_parameters(&parameter_file)?;
+
+    // Name column as wide as the longest name, format widths count characters so the units line up
+    let width = PARAMETER_NAMES.iter().map(|name| name.chars().count()).max().unwrap_or(0).max("Parameter".len());
+    let separator = format!("+-{}-+--------------+--------+", "-".repeat(width));
+
+    println!("{}", separator);
+    println!("| {:<width$} | Value        |        |", "Parameter");
+    println!("{}", separator);
+    for ((key, name), value) in PARAMETER_KEYS.iter().zip(PARAMETER_NAMES).zip(parameter_file.parameters.to_genes()) {
+        let status = if parameter_file.fixed.iter().any(|fixed| fixed == key) { "fixed" } else { "fitted" };
+        println!("| {:<width$} | {:>12.4e} | {:<6} |", name, value, status);
+    }
+    println!("{}", separator);
+    if let Some(fitness) = parameter_file.fitness {
+        println!("| {:<width$} | {:>12.2} |        |", "Fitness", fitness / 10000.0);
+        println!("{}", separator);
+    }
+
+    // Columns of cycle_summary.csv, efficiencies and utilisation in %
+    let mut rdr = Reader::from_path(results.join("cycle_summary.csv"))?;
+
+    println!();
+    println!("| Cycle | Source    | Charge (Ah) | Discharge (Ah) | CE (%) | VE (%) | EE (%) | Util. (%) |");
+    for result in rdr.records() {
+        let record = result?;
+        let value = |index: usize| record[index].trim().parse::<f32>().unwrap_or(f32::NAN);
+
+        println!("| {:>5} | {:<9} | {:>11.3} | {:>14.3} | {:>6.1} | {:>6.1} | {:>6.1} | {:>9.1} |",
+            record[0].trim(), record[1].trim(), value(2), value(3), value(6), value(7), value(8), value(9));
+    }
+
+    Ok(())
+}
+
+fn simulate_protocol(protocol_file: &str, model: &ModelArgs, output_dir: &str) -> Result<(), Box<dyn Error>> {
+    // Run a cycling protocol in silico with a previously fitted parameter set
+    let protocol = read_protocol(protocol_file)?;
+    let parameters = read_parameters(Path::new(&model.parameters))?.parameters;
+    let stack = load_stack_config(&model.stack)?;
+
+    let output_dir = Path::new(output_dir);
+    std::fs::create_dir_all(output_dir)?;
+
+    run_protocol(&protocol, &parameters, &stack.options, &stack,
+        &output_dir.join("protocol_output.csv").to_string_lossy(), &output_dir.join("protocol_capacity.csv").to_string_lossy())?;
+    println!("Protocol {} simulated into protocol_output.csv and protocol_capacity.csv in {}", protocol_file, output_dir.display());
+
+    Ok(())
+}
+
+fn simulate_impedance(state_of_charge: f32, current: f32, temperature: Option<f32>, model: &ModelArgs, output_dir: &str) -> Result<(), Box<dyn Error>> {
+    // Impedance spectrum of a previously fitted parameter set, 1 mHz to 10 kHz
+    let parameters = read_parameters(Path::new(&model.parameters))?.parameters;
+    let stack = load_stack_config(&model.stack)?;
+
+    let operating_point = OperatingPoint {
+        state_of_charge,
+        temperature: temperature.unwrap_or(stack.ambient_temperature),
+        current,
     };
+    check_operating_point(operating_point.state_of_charge, operating_point.temperature)?;
+
+    let frequencies = log_frequencies(1.0e-3, 1.0e4, 10);
+    let impedance = impedance_spectrum(&parameters, &stack.options, &stack, &operating_point, &frequencies);
+
+    let output_dir = Path::new(output_dir);
+    std::fs::create_dir_all(output_dir)?;
+
+    write_spectrum(&output_dir.join("impedance.csv").to_string_lossy(), &frequencies, &impedance)?;
+    println!("Impedance at SOC {}, {} K and {} A written to impedance.csv in {}", state_of_charge, operating_point.temperature, current, output_dir.display());
+
+    Ok(())
+}
+
+fn simulate_polarization(state_of_charge: f32, maximum_current: f32, temperature: Option<f32>, model: &ModelArgs, output_dir: &str) -> Result<(), Box<dyn Error>> {
+    // Polarization curve of a previously fitted parameter set from discharge to charge
+    let parameters = read_parameters(Path::new(&model.parameters))?.parameters;
+    let stack = load_stack_config(&model.stack)?;
+
+    let temperature = temperature.unwrap_or(stack.ambient_temperature);
+    check_operating_point(state_of_charge, temperature)?;
+
+    let currents = current_sweep(maximum_current, 101);
+    let curve = polarization_curve(&parameters, &stack.options, &stack, state_of_charge, temperature, &currents);
+
+    let output_dir = Path::new(output_dir);
+    std::fs::create_dir_all(output_dir)?;
+
+    write_polarization_curve(&output_dir.join("polarization.csv").to_string_lossy(), &curve)?;
+    println!("Polarization curve at SOC {} and {} K up to {} A written to polarization.csv in {}", state_of_charge, temperature, maximum_current, output_dir.display());
+
+    Ok(())
+}
+
+fn load_measured_impedance(path: &str) -> Option<MeasuredImpedance> {
+    if !std::path::Path::new(path).exists() {
+        return None;
+    }
+
+    match MeasuredImpedance::load(path) {
+        Ok(measured) => {
+            println!("Fitting {} impedance points from {} alongside the voltage", measured.frequencies.len(), path);
+            Some(measured)
+        }
+        Err(e) => {
+            eprintln!("Error reading {}: {}, fitting the voltage only", path, e);
+            None
+        }
+    }
+}
+
+fn load_fit_config(path: &str) -> Result<FitConfig, Box<dyn Error>> {
+    // Fall back to the built-in search space when no config file is present
+    if !std::path::Path::new(path).exists() {
+        println!("No {} found, using the default fit settings", path);
+        return Ok(FitConfig::default());
+    }
+
+    let config = FitConfig::load(path).map_err(|e| format!("{}: {}", path, e))?;
+    println!("Loaded fit settings from {}", path);
+
+    Ok(config)
+}
+
+fn fit(args: &FitArgs) -> Result<(), Box<dyn Error>> {
+    let mut config = load_fit_config(&args.config)?;
+
+    // Command-line flags take precedence over the config file
+    if let Some(data) = &args.data {
+        config.data = data.clone();
+    }
+    if let Some(stack) = &args.stack {
+        config.stack = stack.clone();
+    }
+    if let Some(eis) = &args.eis {
+        config.eis = eis.clone();
+    }
+    if let Some(output_dir) = &args.output_dir {
+        config.output_dir = output_dir.clone();
+    }
+    if let Some(population) = args.population {
+        config.genetic_algorithm.population = population;
+    }
+    if let Some(generations) = args.generations {
+        config.genetic_algorithm.generations = generations;
+    }
+    if args.seed.is_some() {
+        config.genetic_algorithm.seed = args.seed;
+    }
+    for name in &args.free {
+        config.free(name)?;
+    }
+    for (name, value) in &args.fix {
+        config.fix(name, *value)?;
+    }
+    config.validate()?;
+
+    // Stack geometry, time step and model options, read from the stack config when present
+    let stack = load_stack_config(&config.stack)?;
+    let options = stack.options;
+
+    // Measured impedance spectrum, added to the fitness when present
+    let measured_impedance = load_measured_impedance(&config.eis);
+
+    // Parameters of switched-off physics cannot change the fitness
+    let unused = config.fix_unused(&options, measured_impedance.is_some());
+    if !unused.is_empty() {
+        println!("Holding {} fixed, unused with the options in {}", unused.join(", "), config.stack);
+    }
+
+    // A fit without a seed still records the one it used
+    let seed = *config.genetic_algorithm.seed.get_or_insert_with(rand::random);
+
+    let output_dir = Path::new(&config.output_dir);
+    std::fs::create_dir_all(output_dir)?;
+
+    // Saved next to the results so the fit can be repeated exactly
+    config.save(&output_dir.join("fit_config.json"))?;
+    println!("Fitting with seed {}, settings saved to {}", seed, output_dir.join("fit_config.json").display());
+
+    // Initialize population
+    let mut population = config.population();
 
     // Generate initial population
-    population.generate_pop(500000);    // Population size
+    population.generate_pop(config.genetic_algorithm.population);
+    population.insert_initial_guess(&config.initial_guess());
 
     let max_gen = population.maximum_generation;
     let mut cur_gen = population.current_generation;
@@ -93,14 +425,17 @@ fn main() {
 
     let start_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
 
-    // Grab the real current and voltage data, only single file read
-    let (real_current, real_voltage) = read_real_data();
+    // Grab the real time, current and voltage data, only single file read
+    let (real_time, real_current, real_voltage) = read_real_data(&config.data)?;
 
     while cur_gen < max_gen {
         // Use larger chunks for better parallel performance
         shared_struct.lock().unwrap().individual_list.par_chunks_mut(1000).for_each(|chunk| {
             chunk.iter_mut().for_each(|individual| {
-                individual[10] = electrochem_model_sim(false, *individual, real_current.clone(), real_voltage.clone());
+                let parameters = ModelParameters::from_genes(&individual.genes);
+
+                individual.fitness = electrochem_model_sim(None, &parameters, &options, &stack, real_time.clone(), real_current.clone(), real_voltage.clone())
+                    + measured_impedance.as_ref().map_or(0.0, |measured| measured.fitness(&parameters, &options, &stack));
             });
         });
 
@@ -110,25 +445,37 @@ fn main() {
         population.preserve_best_solutions();
         population.population_crossover();
         population.mutate_population();
-        
+
         // Preserve best solutions after modification
         population.preserve_best_solutions();
-        
+
         population.current_generation += 1;
         cur_gen += 1;
 
         let best_individual = population.best_fitness_calc();
-        let best_params = population.individual_list[best_individual];
-        
+        let best_individual = population.individual_list[best_individual];
+        let best_params = ModelParameters::from_genes(&best_individual.genes);
+
         // Only write output in the final generation
         if cur_gen == max_gen {
             // Run simulation one final time with output writing enabled for plotting
             electrochem_model_sim(
-                true,  // Enable file writing
-                best_params,
+                Some(output_dir),  // Enable file writing
+                &best_params,
+                &options,
+                &stack,
+                real_time.clone(),
                 real_current.clone(),
                 real_voltage.clone()
             );
+
+            // Keep the fitted parameters for protocol simulations
+            let parameter_file = ParameterFile {
+                fitness: Some(best_individual.fitness),
+                fixed: config.fixed_parameters(),
+                parameters: best_params,
+            };
+            write_parameters(&output_dir.join("best_parameters.json"), &parameter_file)?;
         }
 
         drop(population);
@@ -136,4 +483,26 @@ fn main() {
 
     let end_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
     println!("Total duration: {} s", (end_time - start_time));
+
+    Ok(())
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    let result = match &cli.command {
+        Command::Prepare { input, output } => process_data(input, output)
+            .map(|_| println!("Successfully processed {} into {}", input, output)),
+        Command::Fit(args) => fit(args),
+        Command::Simulate { data, model, output_dir } => simulate_data(data, model, output_dir),
+        Command::Report { results, parameters } => print_report(results, parameters.as_deref()),
+        Command::Protocol { protocol, model, output_dir } => simulate_protocol(protocol, model, output_dir),
+        Command::Impedance { soc, current, temperature, model, output_dir } => simulate_impedance(*soc, *current, *temperature, model, output_dir),
+        Command::Polarization { soc, maximum_current, temperature, model, output_dir } => simulate_polarization(*soc, *maximum_current, *temperature, model, output_dir),
+    };
+
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
 }
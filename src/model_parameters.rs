@@ -0,0 +1,69 @@
+// Named model parameters and their mapping to the gene vector of the optimiser.
+// Every parameter is declared once below, its position in the list is its gene index.
+
+use serde::{Deserialize, Serialize};
+
+/// Gene vector searched by the genetic algorithm, one value per model parameter
+pub type Genes = [f64; GENE_COUNT];
+
+macro_rules! model_parameters {
+    ($($(#[doc = $doc:literal])* $field:ident: $label:literal, $display_scale:literal, $description:literal;)*) => {
+        /// Fitted parameters of the electrochemical model, SI units
+        #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+        pub struct ModelParameters {
+            $($(#[doc = $doc])* pub $field: f64,)*
+        }
+
+        pub const GENE_COUNT: usize = [$(stringify!($field)),*].len();
+
+        /// Name of each parameter in parameter and config files, in gene order
+        pub const PARAMETER_KEYS: [&str; GENE_COUNT] = [$(stringify!($field)),*];
+
+        /// Name and unit of each parameter, in gene order
+        pub const PARAMETER_NAMES: [&str; GENE_COUNT] = [$($description),*];
+
+        /// Short label of each parameter for the progress table and the factor its value is divided by there
+        pub const PARAMETER_LABELS: [(&str, f64); GENE_COUNT] = [$(($label, $display_scale)),*];
+
+        impl ModelParameters {
+            pub fn from_genes(genes: &Genes) -> Self {
+                // Fields are initialised in declaration order, which is the gene order
+                let mut genes = genes.iter().copied();
+
+                ModelParameters {
+                    $($field: genes.next().unwrap(),)*
+                }
+            }
+
+            pub fn to_genes(self) -> Genes {
+                [$(self.$field),*]
+            }
+        }
+    };
+}
+
+model_parameters! {
+    anolyte_c1: "C1a (mol/m³)", 1.0, "Anolyte concentration C1 (mol/m³)";
+    catholyte_c1: "C1c (mol/m³)", 1.0, "Catholyte concentration C1 (mol/m³)";
+    stack_resistance: "R (Ohm)", 1.0, "Stack resistance (Ohm)";
+    rate_constant_positive: "k+ (1e-6 m/s)", 1.0e-6, "Positive rate constant k+ (m/s)";
+    rate_constant_negative: "k- (1e-6 m/s)", 1.0e-6, "Negative rate constant k- (m/s)";
+    diffusion_coefficient: "Dmem (1e-12)", 1.0e-12, "Membrane diffusion coefficient (m²/s)";
+    charge_offset: "Vc (V)", 1.0, "Charge offset (V)";
+    discharge_offset: "Vd (V)", 1.0, "Discharge offset (V)";
+    anolyte_c2: "C2a (mol/m³)", 1.0, "Anolyte concentration C2 (mol/m³)";
+    /// Plated copper expressed per catholyte volume
+    catholyte_c0: "C0c (mol/m³)", 1.0, "Catholyte concentration C0 (mol/m³)";
+    mass_transfer_factor: "km0 (1e-3)", 1.0e-3, "Mass-transfer factor km0 (m/s per (m/s)^0.4)";
+    her_exchange_current: "i0 HER (mA/m²)", 1.0e-3, "HER exchange current density (A/m²)";
+    her_tafel_slope: "b HER (V/dec)", 1.0, "HER Tafel slope (V/decade)";
+    oer_exchange_current: "i0 OER (mA/m²)", 1.0e-3, "Cl2/O2 evolution exchange current density (A/m²)";
+    oer_tafel_slope: "b OER (V/dec)", 1.0, "Cl2/O2 evolution Tafel slope (V/decade)";
+    copper_loss_rate: "kCu (1e-9 /s)", 1.0e-9, "Copper loss rate (1/s)";
+    electrolyte_crossover: "Vx (mL/Ah)", 1.0e-6, "Electrolyte crossover, anolyte to catholyte (m³/Ah)";
+    resistance_growth: "dR (mOhm/Ah)", 1.0e-3, "Stack resistance growth (Ohm/Ah)";
+    electro_osmotic_drag: "nd (H2O/e)", 1.0, "Electro-osmotic drag (mol water/mol charge)";
+    osmotic_permeability: "Los (1e-12)", 1.0e-12, "Osmotic water permeability (m/s per mol/m³)";
+    positive_capacitance: "Cdl+ (F/m²)", 1.0, "Positive double-layer capacitance (F/m²)";
+    negative_capacitance: "Cdl- (F/m²)", 1.0, "Negative double-layer capacitance (F/m²)";
+}
@@ -0,0 +1,64 @@
+// Steady-state polarization curves of the stack at a fixed state of charge and temperature.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use crate::electrochem_model::{ElectrochemicalModel, ModelOptions, VoltageBreakdown};
+use crate::model_parameters::ModelParameters;
+use crate::stack_config::StackConfig;
+
+/// One point of the curve, current positive on charge
+#[derive(Clone, Copy)]
+pub struct PolarizationPoint {
+    pub current: f32,
+    pub breakdown: VoltageBreakdown,
+}
+
+impl PolarizationPoint {
+    pub fn voltage(&self) -> f32 {
+        self.breakdown.voltage()
+    }
+
+    pub fn power(&self) -> f32 {
+        self.current * self.voltage()
+    }
+}
+
+/// Stack voltage and its contributions at each current (A), the electrolyte held at the state of charge.
+/// The tanks are not depleted by the current, every point is taken from the same rested electrolyte.
+pub fn polarization_curve(parameters: &ModelParameters, options: &ModelOptions, stack: &StackConfig, state_of_charge: f32, temperature: f32, currents: &[f32]) -> Vec<PolarizationPoint> {
+    let mut model = ElectrochemicalModel::new(parameters, options, stack);
+
+    currents.iter().map(|&current| {
+        model.set_operating_point(state_of_charge, temperature, current);
+
+        PolarizationPoint {
+            current,
+            breakdown: model.voltage_breakdown(),
+        }
+    }).collect()
+}
+
+/// Evenly spaced currents from full discharge to full charge at the given magnitude (A)
+pub fn current_sweep(maximum_current: f32, points: usize) -> Vec<f32> {
+    let points = points.max(2);
+
+    (0..points).map(|index| -maximum_current + 2.0 * maximum_current * index as f32 / (points - 1) as f32).collect()
+}
+
+pub fn write_polarization_curve(path: &str, curve: &[PolarizationPoint]) -> Result<(), Box<dyn Error>> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "Current (A), Voltage (V), Open circuit (V), Activation (V), Concentration (V), Ohmic (V), Offset (V), Power (W)")?;
+
+    for point in curve {
+        let breakdown = &point.breakdown;
+
+        writeln!(writer, "{}, {}, {}, {}, {}, {}, {}, {}",
+            point.current, point.voltage(), breakdown.open_circuit, breakdown.activation,
+            breakdown.concentration, breakdown.ohmic, breakdown.offset, point.power())?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
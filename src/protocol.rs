@@ -0,0 +1,174 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+
+use serde::Deserialize;
+
+use crate::cycle_metrics::{capacity_per_cycle, write_capacity_report};
+use crate::electrochem_model::{ElectrochemicalModel, ModeController, ModelOptions, OperatingMode};
+use crate::model_parameters::ModelParameters;
+use crate::stack_config::StackConfig;
+
+// Safety net for steps whose cut-offs are never reached
+const MAXIMUM_STEP_DURATION_MINUTES: f32 = 48.0 * 60.0;
+
+/// Cycling schedule read from a JSON protocol file, the steps are repeated for the given number of cycles
+#[derive(Deserialize)]
+pub struct Protocol {
+    pub cycles: u32,
+    pub steps: Vec<ProtocolStep>,
+}
+
+/// One step of a protocol, currents and powers in A and W, voltages for the whole stack.
+/// Each step ends at the first cut-off reached or after its duration.
+#[derive(Deserialize, Clone, Copy)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ProtocolStep {
+    /// Constant current charge
+    Charge {
+        current: f32,
+        voltage_cutoff: Option<f32>,
+        soc_cutoff: Option<f32>,
+        duration_minutes: Option<f32>,
+    },
+    /// Constant current discharge, current given as a magnitude
+    Discharge {
+        current: f32,
+        voltage_cutoff: Option<f32>,
+        soc_cutoff: Option<f32>,
+        duration_minutes: Option<f32>,
+    },
+    /// Constant voltage hold until the current tapers below the cut-off
+    ConstantVoltage {
+        voltage: f32,
+        current_cutoff: f32,
+        duration_minutes: Option<f32>,
+    },
+    /// Constant power, positive on charge
+    ConstantPower {
+        power: f32,
+        voltage_cutoff: Option<f32>,
+        soc_cutoff: Option<f32>,
+        duration_minutes: Option<f32>,
+    },
+    /// Constant current to the voltage limit followed by a voltage hold, negative current for discharge
+    CcCv {
+        current: f32,
+        voltage_limit: f32,
+        current_cutoff: f32,
+        duration_minutes: Option<f32>,
+    },
+    /// Open circuit
+    Rest {
+        duration_minutes: f32,
+    },
+}
+
+impl ProtocolStep {
+    fn operating_mode(&self) -> OperatingMode {
+        match *self {
+            ProtocolStep::Charge { current, .. } => OperatingMode::ConstantCurrent(current.abs()),
+            ProtocolStep::Discharge { current, .. } => OperatingMode::ConstantCurrent(-current.abs()),
+            ProtocolStep::ConstantVoltage { voltage, .. } => OperatingMode::ConstantVoltage(voltage),
+            ProtocolStep::ConstantPower { power, .. } => OperatingMode::ConstantPower(power),
+            ProtocolStep::CcCv { current, voltage_limit, current_cutoff, .. } => {
+                OperatingMode::ConstantCurrentConstantVoltage { current, voltage_limit, current_cutoff }
+            }
+            ProtocolStep::Rest { .. } => OperatingMode::ConstantCurrent(0.0),
+        }
+    }
+
+    fn duration_minutes(&self) -> f32 {
+        let duration = match *self {
+            ProtocolStep::Charge { duration_minutes, .. }
+            | ProtocolStep::Discharge { duration_minutes, .. }
+            | ProtocolStep::ConstantVoltage { duration_minutes, .. }
+            | ProtocolStep::ConstantPower { duration_minutes, .. }
+            | ProtocolStep::CcCv { duration_minutes, .. } => duration_minutes,
+            ProtocolStep::Rest { duration_minutes } => Some(duration_minutes),
+        };
+
+        duration.unwrap_or(MAXIMUM_STEP_DURATION_MINUTES).min(MAXIMUM_STEP_DURATION_MINUTES)
+    }
+
+    fn cutoff_reached(&self, model: &ElectrochemicalModel, controller: &ModeController) -> bool {
+        // Evaluated live after every model step
+        let voltage = model.voltage();
+        let soc = model.state_of_charge();
+
+        match *self {
+            ProtocolStep::Charge { voltage_cutoff, soc_cutoff, .. } => {
+                voltage_cutoff.is_some_and(|cutoff| voltage >= cutoff) || soc_cutoff.is_some_and(|cutoff| soc >= cutoff)
+            }
+            ProtocolStep::Discharge { voltage_cutoff, soc_cutoff, .. } => {
+                voltage_cutoff.is_some_and(|cutoff| voltage <= cutoff) || soc_cutoff.is_some_and(|cutoff| soc <= cutoff)
+            }
+            ProtocolStep::ConstantVoltage { current_cutoff, .. } => model.current().abs() < current_cutoff,
+            ProtocolStep::ConstantPower { power, voltage_cutoff, soc_cutoff, .. } => {
+                if power >= 0.0 {
+                    voltage_cutoff.is_some_and(|cutoff| voltage >= cutoff) || soc_cutoff.is_some_and(|cutoff| soc >= cutoff)
+                } else {
+                    voltage_cutoff.is_some_and(|cutoff| voltage <= cutoff) || soc_cutoff.is_some_and(|cutoff| soc <= cutoff)
+                }
+            }
+            ProtocolStep::CcCv { .. } => controller.taper_finished(),
+            ProtocolStep::Rest { .. } => false,
+        }
+    }
+}
+
+pub fn read_protocol(path: &str) -> Result<Protocol, Box<dyn Error>> {
+    let reader = BufReader::new(File::open(path)?);
+    let protocol: Protocol = serde_json::from_reader(reader)?;
+
+    Ok(protocol)
+}
+
+/// Execute the protocol against the model, writing the time series to output_path and the per-cycle capacities to capacity_path
+pub fn run_protocol(protocol: &Protocol, parameters: &ModelParameters, options: &ModelOptions, stack: &StackConfig, output_path: &str, capacity_path: &str) -> Result<(), Box<dyn Error>> {
+    let mut model = ElectrochemicalModel::new(parameters, options, stack);
+
+    let mut writer = BufWriter::new(File::create(output_path)?);
+    writeln!(writer, "Time, Cycle, Step, Current, Voltage, SOC, Temperature")?;
+
+    let mut time_counter: f32 = 0.0;
+
+    // Kept for the capacity report
+    let mut time_data = Vec::new();
+    let mut current_data = Vec::new();
+    let mut cycle_data = Vec::new();
+
+    for cycle in 0..protocol.cycles {
+        for (step_index, step) in protocol.steps.iter().enumerate() {
+            let mode = step.operating_mode();
+            let mut controller = ModeController::default();
+            let mut step_time: f32 = 0.0;
+
+            while step_time < step.duration_minutes() * 60.0 {
+                let current = controller.current(&model, mode, model.time_step_size());
+                model.advance(current);
+
+                step_time += model.time_step_size();
+                time_counter += model.time_step_size();
+
+                writeln!(writer, "{}, {}, {}, {}, {}, {}, {}",
+                    time_counter, cycle, step_index, model.current(), model.voltage(),
+                    model.state_of_charge(), model.temperature())?;
+
+                time_data.push(time_counter);
+                current_data.push(model.current());
+                cycle_data.push(cycle);
+
+                if step.cutoff_reached(&model, &controller) {
+                    break;
+                }
+            }
+        }
+    }
+
+    writer.flush()?;
+
+    write_capacity_report(capacity_path, &capacity_per_cycle(&time_data, &current_data, &cycle_data))?;
+
+    Ok(())
+}
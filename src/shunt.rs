@@ -0,0 +1,117 @@
+// Shunt currents through the electrolyte in the shared manifolds.
+// Every cell's electrolyte connects through its channels to a manifold ladder running along the
+// stack, so cells at different potentials discharge into each other even at open circuit.
+
+use crate::stack_config::ShuntConfig;
+
+/// Current leaving each cell's electrolyte through its channels (A), cells ordered from the
+/// negative end of the stack, given the voltage of every cell
+pub fn shunt_currents(cell_voltages: &[f32], config: &ShuntConfig) -> Vec<f32> {
+    // Electrolyte of a cell sits halfway between its two bipolar plates
+    let mut node_potentials = Vec::with_capacity(cell_voltages.len());
+    let mut plate_potential = 0.0;
+    for voltage in cell_voltages {
+        node_potentials.push(plate_potential + voltage / 2.0);
+        plate_potential += voltage;
+    }
+
+    // Feed and return manifolds carry the same ladder in parallel, halving both resistances
+    let anolyte = ladder_currents(&node_potentials, config.anolyte_channel_resistance / 2.0, config.anolyte_manifold_resistance / 2.0);
+    let catholyte = ladder_currents(&node_potentials, config.catholyte_channel_resistance / 2.0, config.catholyte_manifold_resistance / 2.0);
+
+    anolyte.iter().zip(&catholyte).map(|(anolyte, catholyte)| anolyte + catholyte).collect()
+}
+
+/// Power dissipated in the manifolds and channels (W)
+pub fn shunt_loss(cell_voltages: &[f32], shunt_currents: &[f32]) -> f32 {
+    // The network is passive, everything the cells push into it is lost as heat
+    let mut plate_potential = 0.0;
+    let mut loss = 0.0;
+    for (voltage, current) in cell_voltages.iter().zip(shunt_currents) {
+        loss += (plate_potential + voltage / 2.0) * current;
+        plate_potential += voltage;
+    }
+
+    loss
+}
+
+fn ladder_currents(node_potentials: &[f32], channel_resistance: f32, manifold_resistance: f32) -> Vec<f32> {
+    // Current balance on each manifold node, tridiagonal in the manifold potentials
+    //   (φk - mk) / Rc + (mk-1 - mk) / Rm + (mk+1 - mk) / Rm = 0
+    let count = node_potentials.len();
+    if count == 0 {
+        return Vec::new();
+    }
+
+    let channel_conductance = 1.0 / channel_resistance;
+    let manifold_conductance = 1.0 / manifold_resistance;
+
+    let mut diagonal = vec![channel_conductance; count];
+    let mut right_hand_side: Vec<f32> = node_potentials.iter().map(|potential| potential * channel_conductance).collect();
+    for (index, value) in diagonal.iter_mut().enumerate() {
+        if index > 0 {
+            *value += manifold_conductance;
+        }
+        if index + 1 < count {
+            *value += manifold_conductance;
+        }
+    }
+
+    // Thomas algorithm, the off-diagonals are all -1/Rm
+    for index in 1..count {
+        let factor = -manifold_conductance / diagonal[index - 1];
+        diagonal[index] += factor * manifold_conductance;
+        right_hand_side[index] -= factor * right_hand_side[index - 1];
+    }
+
+    let mut manifold_potentials = vec![0.0; count];
+    manifold_potentials[count - 1] = right_hand_side[count - 1] / diagonal[count - 1];
+    for index in (0..count - 1).rev() {
+        manifold_potentials[index] = (right_hand_side[index] + manifold_conductance * manifold_potentials[index + 1]) / diagonal[index];
+    }
+
+    node_potentials.iter().zip(&manifold_potentials)
+        .map(|(node, manifold)| (node - manifold) * channel_conductance)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: ShuntConfig = ShuntConfig {
+        anolyte_channel_resistance: 200.0,
+        anolyte_manifold_resistance: 2.0,
+        catholyte_channel_resistance: 300.0,
+        catholyte_manifold_resistance: 3.0,
+    };
+
+    #[test]
+    fn uniform_stack_is_antisymmetric_about_its_centre() {
+        // Identical cells leak out at the positive end and back in at the negative end
+        let cell_voltages = [1.2; 21];
+        let currents = shunt_currents(&cell_voltages, &CONFIG);
+
+        // Single precision round-off in the ladder solve, relative to the largest current
+        let tolerance = 1.0e-4 * currents[0].abs();
+        for (first, last) in currents.iter().zip(currents.iter().rev()) {
+            assert!((first + last).abs() < tolerance, "{} against {}", first, last);
+        }
+        assert!(currents[10].abs() < tolerance);
+        assert!(currents[0] < 0.0 && currents[20] > 0.0);
+        assert!(currents.iter().sum::<f32>().abs() < tolerance);
+    }
+
+    #[test]
+    fn loss_is_the_power_into_the_network() {
+        let cell_voltages = [1.2; 21];
+        let currents = shunt_currents(&cell_voltages, &CONFIG);
+
+        // Cell k's electrolyte sits at (k + 1/2) cell voltages above the negative end
+        let expected: f32 = currents.iter().enumerate().map(|(index, current)| (index as f32 + 0.5) * 1.2 * current).sum();
+        let loss = shunt_loss(&cell_voltages, &currents);
+
+        assert!(loss > 0.0);
+        assert!((loss - expected).abs() < 1.0e-4 * expected, "{} against {}", loss, expected);
+    }
+}
@@ -0,0 +1,154 @@
+// Chloride complexation of Cu(I) and Cu(II) in concentrated chloride electrolytes.
+// Only the free (uncomplexed) ions enter the Nernst and exchange current terms.
+
+use serde::{Deserialize, Serialize};
+
+const BISECTION_ITERATIONS: usize = 60;
+
+/// Total chloride and cumulative stability constants of the copper chloro-complexes.
+/// Missing fields in the speciation block of stack.json take the literature values at 25 °C.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChlorideSpeciation {
+    /// Total chloride concentration (mol/m³)
+    pub total_chloride: f32,
+    /// Cumulative stability constants β1..β3 of CuCl, CuCl2⁻ and CuCl3²⁻ ((L/mol)^n)
+    pub cuprous_stability: [f32; 3],
+    /// Cumulative stability constants β1..β4 of CuCl⁺, CuCl2, CuCl3⁻ and CuCl4²⁻ ((L/mol)^n)
+    pub cupric_stability: [f32; 4],
+    /// Cell standard potential for the free ions, E°(Cu²⁺/Cu⁺) - E°(Cu⁺/Cu) (V)
+    pub standard_potential: f32,
+}
+
+impl Default for ChlorideSpeciation {
+    fn default() -> Self {
+        // Literature log β values at 25 °C
+        Self {
+            total_chloride: 5000.0,
+            cuprous_stability: [10.0_f32.powf(2.70), 10.0_f32.powf(5.50), 10.0_f32.powf(5.70)],
+            cupric_stability: [10.0_f32.powf(0.43), 10.0_f32.powf(0.16), 10.0_f32.powf(-2.29), 10.0_f32.powf(-4.59)],
+            standard_potential: 0.153 - 0.521,
+        }
+    }
+}
+
+impl ChlorideSpeciation {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.total_chloride <= 0.0 {
+            return Err("speciation total_chloride must be positive".to_string());
+        }
+        if self.cuprous_stability.iter().chain(&self.cupric_stability).any(|&beta| beta <= 0.0) {
+            return Err("speciation stability constants must be positive".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Free Cu(I) and Cu(II) concentrations (mol/m³) for the given total copper concentrations (mol/m³)
+    pub fn free_concentrations(&self, cuprous: f32, cupric: f32) -> (f32, f32) {
+        // Stability constants are on the molar scale
+        let cuprous = cuprous.max(0.0) / 1000.0;
+        let cupric = cupric.max(0.0) / 1000.0;
+        let total_chloride = self.total_chloride / 1000.0;
+
+        // Bound chloride increases monotonically with free chloride, bisect the chloride balance
+        let mut low = 0.0;
+        let mut high = total_chloride;
+
+        for _ in 0..BISECTION_ITERATIONS {
+            let free_chloride = 0.5 * (low + high);
+            let bound_chloride = cuprous * bound_ligands(&self.cuprous_stability, free_chloride)
+                + cupric * bound_ligands(&self.cupric_stability, free_chloride);
+
+            if free_chloride + bound_chloride > total_chloride {
+                high = free_chloride;
+            } else {
+                low = free_chloride;
+            }
+        }
+
+        let free_chloride = 0.5 * (low + high);
+
+        (cuprous * free_fraction(&self.cuprous_stability, free_chloride) * 1000.0,
+         cupric * free_fraction(&self.cupric_stability, free_chloride) * 1000.0)
+    }
+}
+
+fn free_fraction(stability: &[f32], free_chloride: f32) -> f32 {
+    // Fraction of the metal present as the uncomplexed ion, 1 / (1 + Σ βn [Cl]^n)
+    let mut complexed = 0.0;
+    for (index, beta) in stability.iter().enumerate() {
+        complexed += beta * free_chloride.powi(index as i32 + 1);
+    }
+
+    1.0 / (1.0 + complexed)
+}
+
+fn bound_ligands(stability: &[f32], free_chloride: f32) -> f32 {
+    // Average number of chloride ligands per metal ion, Σ n βn [Cl]^n / (1 + Σ βn [Cl]^n)
+    let mut complexed = 0.0;
+    let mut ligands = 0.0;
+    for (index, beta) in stability.iter().enumerate() {
+        let term = beta * free_chloride.powi(index as i32 + 1);
+        complexed += term;
+        ligands += (index + 1) as f32 * term;
+    }
+
+    ligands / (1.0 + complexed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn complexes(stability: &[f32], free_metal: f64, free_chloride: f64) -> Vec<f64> {
+        // Concentration of each chloro-complex, βn [M] [Cl]^n
+        stability.iter().enumerate().map(|(index, &beta)| beta as f64 * free_metal * free_chloride.powi(index as i32 + 1)).collect()
+    }
+
+    #[test]
+    fn solved_speciation_satisfies_the_balances() {
+        // Molar scale, 1.5 M Cu(I) and 0.5 M Cu(II) in 5 M chloride
+        let speciation = ChlorideSpeciation::default();
+        let (cuprous, cupric, total_chloride) = (1.5, 0.5, 5.0);
+        let (free_cuprous, free_cupric) = speciation.free_concentrations(1500.0, 500.0);
+        let (free_cuprous, free_cupric) = (free_cuprous as f64 / 1000.0, free_cupric as f64 / 1000.0);
+        assert!(free_cuprous > 0.0 && free_cuprous < cuprous && free_cupric > 0.0 && free_cupric < cupric);
+
+        // Free chloride that closes the Cu(I) balance, the complexed fraction rises with it
+        let (mut low, mut high) = (0.0, total_chloride);
+        for _ in 0..100 {
+            let chloride = 0.5 * (low + high);
+            let total = free_cuprous + complexes(&speciation.cuprous_stability, free_cuprous, chloride).iter().sum::<f64>();
+            if total > cuprous { high = chloride } else { low = chloride }
+        }
+        let free_chloride = 0.5 * (low + high);
+
+        // Cu(II) balance at the same free chloride
+        let cupric_complexes = complexes(&speciation.cupric_stability, free_cupric, free_chloride);
+        let cupric_total = free_cupric + cupric_complexes.iter().sum::<f64>();
+        assert!((cupric_total - cupric).abs() < 1.0e-3 * cupric, "{} M Cu(II) against {} M", cupric_total, cupric);
+
+        // Chloride balance, free plus n ligands on every complex
+        let cuprous_complexes = complexes(&speciation.cuprous_stability, free_cuprous, free_chloride);
+        let bound: f64 = cuprous_complexes.iter().chain(&cupric_complexes).zip([1.0, 2.0, 3.0, 1.0, 2.0, 3.0, 4.0]).map(|(complex, ligands)| ligands * complex).sum();
+        let chloride_total = free_chloride + bound;
+        assert!((chloride_total - total_chloride).abs() < 1.0e-3 * total_chloride, "{} M chloride against {} M", chloride_total, total_chloride);
+    }
+
+    #[test]
+    fn no_copper_leaves_nothing_free() {
+        assert_eq!(ChlorideSpeciation::default().free_concentrations(0.0, 0.0), (0.0, 0.0));
+    }
+
+    #[test]
+    fn validate_rejects_non_positive_constants() {
+        let defaults = ChlorideSpeciation::default();
+        assert!(defaults.validate().is_ok());
+
+        assert!(ChlorideSpeciation { total_chloride: 0.0, ..defaults }.validate().is_err());
+        assert!(ChlorideSpeciation { total_chloride: -1.0, ..defaults }.validate().is_err());
+        assert!(ChlorideSpeciation { cuprous_stability: [501.2, 0.0, 501187.2], ..defaults }.validate().is_err());
+        assert!(ChlorideSpeciation { cupric_stability: [2.69, 1.45, -0.00513, 0.0000257], ..defaults }.validate().is_err());
+    }
+}
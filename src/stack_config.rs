@@ -0,0 +1,210 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::electrochem_model::ModelOptions;
+use crate::integrator::Integrator;
+
+/// Stack geometry and operating settings, SI units throughout.
+/// Missing fields in a config file fall back to the 30-cell pilot stack.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StackConfig {
+    /// Number of cells in series
+    pub cells: u32,
+    /// Active membrane width, across the flow direction (m)
+    pub membrane_width: f32,
+    /// Active membrane length, along the flow direction (m)
+    pub membrane_length: f32,
+    pub membrane_thickness: f32,
+    /// Compressed felt thickness of each half-cell (m)
+    pub electrode_thickness: f32,
+    /// Total electrolyte on each side, stack and tank (m³)
+    pub anolyte_volume: f32,
+    pub catholyte_volume: f32,
+    /// Volumetric flow rate through the stack on each side (m³/s)
+    pub flow_rate: f32,
+    /// Model time step (s)
+    pub time_step: f32,
+    /// Integration of the model equations over each time step
+    pub integrator: Integrator,
+    pub ambient_temperature: f32,
+    /// Heat loss from stack and tanks to ambient (W/K)
+    pub heat_transfer_coefficient: f32,
+    /// Cell-to-cell variation, all cells identical when absent
+    pub cell_variation: Option<CellVariation>,
+    /// Shunt currents through the shared manifolds, ignored when absent
+    pub shunt: Option<ShuntConfig>,
+    /// Optional model physics
+    pub options: ModelOptions,
+}
+
+/// Electrolyte resistances of the shunt paths, feed and return sides are taken as identical
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct ShuntConfig {
+    /// Channel between one cell and the anolyte manifold (Ohm)
+    pub anolyte_channel_resistance: f32,
+    /// Anolyte manifold between two neighbouring cells (Ohm)
+    pub anolyte_manifold_resistance: f32,
+    /// Channel between one cell and the catholyte manifold (Ohm)
+    pub catholyte_channel_resistance: f32,
+    /// Catholyte manifold between two neighbouring cells (Ohm)
+    pub catholyte_manifold_resistance: f32,
+}
+
+/// How the properties of the individual cells deviate from the fitted stack values
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CellVariation {
+    /// Lognormal factors drawn per cell, given as the standard deviation of the factor's logarithm
+    Random {
+        resistance: f32,
+        rate_constant: f32,
+        diffusion: f32,
+        membrane_thickness: f32,
+        seed: u64,
+    },
+    /// Factors listed for every cell, from the first cell to the last
+    Explicit {
+        cells: Vec<CellFactors>,
+    },
+}
+
+/// Multipliers applied to one cell's share of the stack parameters, 1.0 is a nominal cell
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CellFactors {
+    pub resistance: f32,
+    pub rate_constant_positive: f32,
+    pub rate_constant_negative: f32,
+    pub diffusion: f32,
+    pub membrane_thickness: f32,
+}
+
+impl Default for CellFactors {
+    fn default() -> Self {
+        Self {
+            resistance: 1.0,
+            rate_constant_positive: 1.0,
+            rate_constant_negative: 1.0,
+            diffusion: 1.0,
+            membrane_thickness: 1.0,
+        }
+    }
+}
+
+impl Default for StackConfig {
+    fn default() -> Self {
+        Self {
+            cells: 30,
+            membrane_width: 28.0 / 100.0,
+            membrane_length: 32.0 / 100.0,
+            membrane_thickness: 60e-6,
+            electrode_thickness: 4.0e-3,
+            anolyte_volume: 0.06,
+            catholyte_volume: 0.06,
+            flow_rate: 8.0e-3 / 60.0,
+            time_step: 60.0,
+            integrator: Integrator::default(),
+            ambient_temperature: 298.15,
+            heat_transfer_coefficient: 10.0,
+            cell_variation: None,
+            shunt: None,
+            options: ModelOptions::default(),
+        }
+    }
+}
+
+impl StackConfig {
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let reader = BufReader::new(File::open(path)?);
+        let config: StackConfig = serde_json::from_reader(reader)?;
+        config.validate()?;
+
+        if let Some(CellVariation::Explicit { cells }) = &config.cell_variation {
+            if cells.len() != config.cells as usize {
+                return Err(format!("cell_variation lists {} cells, the stack has {}", cells.len(), config.cells).into());
+            }
+        }
+        if let Some(speciation) = &config.options.speciation {
+            speciation.validate()?;
+        }
+
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.cells == 0 {
+            return Err("cells must be at least 1".to_string());
+        }
+
+        let positive = [
+            ("membrane_width", self.membrane_width),
+            ("membrane_length", self.membrane_length),
+            ("membrane_thickness", self.membrane_thickness),
+            ("electrode_thickness", self.electrode_thickness),
+            ("flow_rate", self.flow_rate),
+            ("time_step", self.time_step),
+            ("ambient_temperature", self.ambient_temperature),
+        ];
+        if let Some((name, _)) = positive.iter().find(|(_, value)| *value <= 0.0) {
+            return Err(format!("{} must be positive", name));
+        }
+        if self.heat_transfer_coefficient < 0.0 {
+            return Err("heat_transfer_coefficient must not be negative".to_string());
+        }
+
+        // The tanks hold what the stack does not, they and the stack make up the thermal mass
+        let hold_up = self.half_cell_volume() * self.cells as f32;
+        for (name, volume) in [("anolyte_volume", self.anolyte_volume), ("catholyte_volume", self.catholyte_volume)] {
+            if volume <= hold_up {
+                return Err(format!("{} of {} m³ must exceed the stack hold-up of {} m³", name, volume, hold_up));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Factors of every cell in the stack, None when all cells are identical
+    pub fn cell_factors(&self) -> Option<Vec<CellFactors>> {
+        match self.cell_variation.as_ref()? {
+            CellVariation::Explicit { cells } => Some(cells.clone()),
+            CellVariation::Random { resistance, rate_constant, diffusion, membrane_thickness, seed } => {
+                // Seeded so every individual of a fit sees the same stack
+                let mut rng = StdRng::seed_from_u64(*seed);
+
+                let factors = (0..self.cells).map(|_| CellFactors {
+                    resistance: lognormal_factor(&mut rng, *resistance),
+                    rate_constant_positive: lognormal_factor(&mut rng, *rate_constant),
+                    rate_constant_negative: lognormal_factor(&mut rng, *rate_constant),
+                    diffusion: lognormal_factor(&mut rng, *diffusion),
+                    membrane_thickness: lognormal_factor(&mut rng, *membrane_thickness),
+                }).collect();
+
+                Some(factors)
+            }
+        }
+    }
+
+    pub fn membrane_surface_area(&self) -> f32 {
+        self.membrane_width * self.membrane_length
+    }
+
+    /// Electrolyte held in one half-cell (m³)
+    pub fn half_cell_volume(&self) -> f32 {
+        self.membrane_surface_area() * self.electrode_thickness
+    }
+}
+
+fn lognormal_factor(rng: &mut StdRng, sigma: f32) -> f32 {
+    // Box-Muller standard normal, exponentiated so the factor stays positive with a median of one
+    let u1: f32 = rng.gen_range(f32::EPSILON..1.0);
+    let u2: f32 = rng.gen();
+    let normal = (-2.0 * u1.ln()).sqrt() * (2.0 * std::f32::consts::PI * u2).cos();
+
+    (sigma * normal).exp()
+}